@@ -0,0 +1,179 @@
+use crate::owns_lib::{pkg_owner, update_owners_cache};
+use crate::shared_lib::{
+    globals::Config, is_symlink, read_a_dir_and_sort, read_a_files_lines, tmp_file, KissError,
+};
+
+// logging
+use crate::log;
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+// threading
+use crate::sort_reverse;
+
+// convert a choice file name (pkg>usr>bin>ls) to (pkg, /usr/bin/ls)
+fn choice_to_path(choice: &str) -> Option<(String, String)> {
+    let (pkg, path) = choice.split_once('>')?;
+
+    Some((pkg.to_owned(), format!("/{}", path.replace('>', "/"))))
+}
+
+// convert (pkg, /usr/bin/ls) to a choice file name (pkg>usr>bin>ls)
+pub fn path_to_choice(pkg: &str, path: &str) -> String {
+    format!("{}>{}", pkg, path.trim_start_matches('/').replace('/', ">"))
+}
+
+// Replace the matching line in the manifest with the desired replacement.
+pub fn pkg_manifest_replace(
+    config: &Config,
+    pkg: &str,
+    old: &str,
+    new: &str,
+) -> Result<(), KissError> {
+    let manifest_path: PathBuf = config.sys_db.join(pkg).join("manifest");
+
+    let mut manifest: Vec<String> = read_a_files_lines(&manifest_path)?
+        .into_iter()
+        .map(|line| if line == old { new.to_owned() } else { line })
+        .collect();
+
+    sort_reverse!(manifest);
+
+    // write to a tmp file first so we never end up with a half written manifest
+    let (mut tmp_file, tmp_file_path) = tmp_file(config, pkg, "manifest-replace")?;
+    for line in manifest {
+        writeln!(tmp_file, "{}", line)?;
+    }
+
+    fs::copy(&tmp_file_path, &manifest_path)?;
+    fs::remove_file(tmp_file_path)?;
+    update_owners_cache(config, pkg);
+
+    Ok(())
+}
+
+// list every alternative in the choices directory
+pub fn pkg_alternatives(config: &Config) -> Result<(), KissError> {
+    let choices: Vec<PathBuf> = read_a_dir_and_sort(
+        config
+            .kiss_root
+            .join(&config.cho_db)
+            .to_string_lossy()
+            .to_string(),
+        false,
+        &[],
+    )?;
+
+    for choice in choices {
+        if !choice.is_file() && !is_symlink(&choice) {
+            continue;
+        }
+
+        let file_name: String = match choice.file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => continue,
+        };
+
+        if let Some((pkg, path)) = choice_to_path(&file_name) {
+            println!("{} {}", pkg, path);
+        }
+    }
+
+    Ok(())
+}
+
+// Swap between package alternatives.
+pub fn pkg_swap(config: &Config, pkg: &str, path: &str) -> Result<(), KissError> {
+    if !config.sys_db.join(pkg).is_dir() {
+        return Err(KissError::NotInstalled(pkg.to_owned()));
+    }
+
+    let path: String = format!("/{}", path.trim_start_matches('/'));
+    let choices_dir: PathBuf = config.kiss_root.join(&config.cho_db);
+
+    let alt: String = path_to_choice(pkg, &path);
+    let alt_path: PathBuf = choices_dir.join(&alt);
+
+    if !alt_path.is_file() && !is_symlink(&alt_path) {
+        return Err(KissError::Other(format!(
+            "Alternative '{} {}' doesn't exist",
+            pkg, path
+        )));
+    }
+
+    let real_path: PathBuf = config.kiss_root.join(path.trim_start_matches('/'));
+
+    if real_path.is_file() || is_symlink(&real_path) {
+        let owner: String = pkg_owner(config, &path)?.ok_or_else(|| {
+            KissError::Other(format!(
+                "File '{}' exists on filesystem but isn't owned",
+                path
+            ))
+        })?;
+
+        log!(format!("Swapping '{}' from '{}' to '{}'", path, owner, pkg));
+
+        // Convert the current owner to an alternative and rewrite its
+        // manifest file to reflect this.
+        let owner_alt: String = path_to_choice(&owner, &path);
+        copy_preserving_symlink(&real_path, &choices_dir.join(&owner_alt))?;
+
+        pkg_manifest_replace(
+            config,
+            &owner,
+            &path,
+            &format!("/{}/{}", config.cho_db, owner_alt),
+        )?;
+    }
+
+    // Convert the desired alternative to a real file and rewrite the
+    // manifest file to reflect this. The reverse of above.
+    if let Some(parent) = real_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&alt_path, &real_path)?;
+
+    pkg_manifest_replace(config, pkg, &format!("/{}/{}", config.cho_db, alt), &path)?;
+
+    Ok(())
+}
+
+// like 'cp -Pf'
+fn copy_preserving_symlink(source: &Path, dest: &Path) -> Result<(), io::Error> {
+    if is_symlink(dest) || dest.exists() {
+        fs::remove_file(dest)?;
+    }
+
+    if is_symlink(source) {
+        symlink(fs::read_link(source)?, dest)?;
+    } else {
+        fs::copy(source, dest)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choice_round_trip() {
+        let choice: String = path_to_choice("busybox", "/usr/bin/ls");
+        assert_eq!(choice, "busybox>usr>bin>ls");
+        assert_eq!(
+            choice_to_path(&choice),
+            Some(("busybox".to_owned(), "/usr/bin/ls".to_owned()))
+        );
+    }
+
+    #[test]
+    fn invalid_choice() {
+        assert_eq!(choice_to_path("busybox"), None);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::checksum_lib::get_file_hash;
+use crate::download_lib::{pkg_download_all, Download, HttpClient};
+use crate::search_lib::{pkg_cache, pkg_find_version};
+use crate::shared_lib::{globals::Config, KissError};
+use crate::source_lib::{pkg_source_local, Compression};
+
+// logging
+use crate::log;
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// a line of a binary repository's index, '<b3sum> <tarball>' like the output
+// of b3sum
+#[derive(Debug, PartialEq, Eq)]
+struct IndexEntry {
+    checksum: String,
+    tarball: String,
+}
+
+// indexes are only fetched once per run, an index which failed to download
+// is empty
+static INDEXES: Lazy<Mutex<HashMap<String, Vec<IndexEntry>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// tarball of pkg from the binary cache, fetched from KISS_BINREPO if it
+// was never built
+pub fn pkg_cache_or_fetch(config: &Config, pkg: &str) -> Result<Option<String>, KissError> {
+    if let Some(tarball) = pkg_cache(config, pkg)? {
+        return Ok(Some(tarball));
+    }
+
+    let version: String =
+        pkg_find_version(config, pkg, None).ok_or_else(|| KissError::NotFound(pkg.to_owned()))?;
+
+    pkg_binrepo_fetch(config, pkg, &version)
+}
+
+// look for pkg@version in every repository of KISS_BINREPO and download the
+// first tarball found into the binary cache
+//
+// a repository which can't be reached is skipped so the package is built
+// instead, a tarball which doesn't match its checksum is an error.
+pub fn pkg_binrepo_fetch(
+    config: &Config,
+    pkg: &str,
+    version: &str,
+) -> Result<Option<String>, KissError> {
+    let prefix: String = format!("{}@{}.tar.", pkg, version);
+    let preferred: String = format!("{}{}", prefix, config.kiss_compress);
+
+    for repo in &config.binrepo {
+        let repo: &str = repo.trim_end_matches('/');
+
+        // only local repositories can be used offline
+        if config.offline && !repo.starts_with("file://") && !repo.starts_with('/') {
+            continue;
+        }
+
+        let (tarball, checksum): (String, String) = {
+            let mut indexes = INDEXES.lock().unwrap_or_else(|err| err.into_inner());
+            let index: &Vec<IndexEntry> = indexes
+                .entry(repo.to_owned())
+                .or_insert_with(|| binrepo_index(config, repo));
+
+            let file_name = |entry: &&IndexEntry| -> String {
+                Path::new(&entry.tarball)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            };
+            let entry: Option<&IndexEntry> = index
+                .iter()
+                .find(|entry| file_name(entry) == preferred)
+                .or_else(|| {
+                    index.iter().find(|entry| {
+                        file_name(entry)
+                            .strip_prefix(prefix.as_str())
+                            .and_then(Compression::from_extension)
+                            .is_some()
+                    })
+                });
+
+            match entry {
+                Some(entry) => (entry.tarball.to_owned(), entry.checksum.to_owned()),
+                None => continue,
+            }
+        };
+
+        let url: String = format!("{}/{}", repo, tarball);
+        let destination: PathBuf = config.bin_dir.join(
+            Path::new(&tarball)
+                .file_name()
+                .unwrap_or_else(|| tarball.as_ref()),
+        );
+
+        // the tarball is only moved into the binary cache once it is verified
+        let mut unverified: PathBuf = destination.to_owned();
+        unverified.as_mut_os_string().push(".unverified");
+
+        log!(pkg, "Found pre-built binary in", repo);
+
+        let result: Result<(), KissError> = match local_path(&url) {
+            Some(source) => pkg_source_local(pkg, &source, &unverified),
+            None => pkg_download_all(
+                config,
+                vec![Download {
+                    pkg: pkg.to_owned(),
+                    source: url.to_owned(),
+                    destination: unverified.to_owned(),
+                }],
+            ),
+        };
+        if let Err(err) = result {
+            log!(pkg, "Failed to fetch pre-built binary:", err);
+            continue;
+        }
+
+        // the index can list plain b3sum output, which is the start of the
+        // longer hashes kiss uses
+        let actual: String = get_file_hash(&unverified.to_string_lossy())?;
+        if checksum.len() < 64 || !actual.starts_with(checksum.as_str()) {
+            let _ = fs::remove_file(&unverified);
+            return Err(KissError::ChecksumMismatch {
+                pkg: pkg.to_owned(),
+                mismatches: vec![(url, checksum, actual)],
+            });
+        }
+
+        fs::rename(&unverified, &destination)?;
+
+        return Ok(Some(destination.to_string_lossy().to_string()));
+    }
+
+    Ok(None)
+}
+
+// download and parse <repo>/index
+fn binrepo_index(config: &Config, repo: &str) -> Vec<IndexEntry> {
+    let url: String = format!("{}/index", repo);
+
+    let content: Result<String, KissError> = match local_path(&url) {
+        Some(path) => fs::read_to_string(path).map_err(KissError::from),
+        None => HttpClient::new(config).and_then(|client| {
+            let mut content: String = String::new();
+            client
+                .get(config, &url)
+                .call()?
+                .into_reader()
+                .read_to_string(&mut content)?;
+            Ok(content)
+        }),
+    };
+
+    match content {
+        Ok(content) => parse_index(&content),
+        Err(err) => {
+            log!(repo, "Failed to fetch index:", err);
+            Vec::new()
+        }
+    }
+}
+
+fn parse_index(content: &str) -> Vec<IndexEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let checksum: &str = fields.next()?;
+            // b3sum marks files read in binary mode with '*'
+            let tarball: &str = fields.next()?.trim_start_matches('*');
+            Some(IndexEntry {
+                checksum: checksum.to_lowercase(),
+                tarball: tarball.to_owned(),
+            })
+        })
+        .collect()
+}
+
+// path of a file:// url or an absolute path
+fn local_path(url: &str) -> Option<PathBuf> {
+    match url.strip_prefix("file://") {
+        Some(path) => Some(PathBuf::from(path)),
+        None if url.starts_with('/') => Some(PathBuf::from(url)),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(checksum: &str, tarball: &str) -> IndexEntry {
+        IndexEntry {
+            checksum: checksum.to_owned(),
+            tarball: tarball.to_owned(),
+        }
+    }
+
+    #[test]
+    fn index_lines() {
+        let index: &str = "AF1349B9  foo@1.0-1.tar.gz\n\
+                           af1349b9 *bar/bar@2.0-1.tar.xz\n\
+                           \n\
+                           incomplete\n";
+
+        assert_eq!(
+            parse_index(index),
+            vec![
+                entry("af1349b9", "foo@1.0-1.tar.gz"),
+                entry("af1349b9", "bar/bar@2.0-1.tar.xz"),
+            ]
+        );
+    }
+
+    #[test]
+    fn local_paths() {
+        assert_eq!(
+            local_path("file:///srv/bin/index"),
+            Some(PathBuf::from("/srv/bin/index"))
+        );
+        assert_eq!(
+            local_path("/srv/bin/index"),
+            Some(PathBuf::from("/srv/bin/index"))
+        );
+        assert_eq!(local_path("https://example.org/bin/index"), None);
+    }
+
+    #[test]
+    fn fetch_from_local_repository() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-binrepo-fetch", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("repo")).unwrap();
+
+        let mut index: String = String::new();
+        for (pkg, checksum) in [
+            ("good", None),
+            ("bad", Some("0".repeat(64))),
+            ("short", None),
+        ] {
+            let tarball: PathBuf = dir.join("repo").join(format!("{}@1.0-1.tar.gz", pkg));
+            fs::write(&tarball, pkg).unwrap();
+            let hash: String = get_file_hash(&tarball.to_string_lossy()).unwrap();
+            let checksum: String = match (pkg, checksum) {
+                (_, Some(checksum)) => checksum,
+                ("short", _) => hash[..8].to_owned(),
+                // plain b3sum output
+                _ => hash[..64].to_owned(),
+            };
+            index.push_str(&format!("{}  {}@1.0-1.tar.gz\n", checksum, pkg));
+        }
+        fs::write(dir.join("repo").join("index"), index).unwrap();
+
+        let mut config: Config = Config::new();
+        config.binrepo = vec![dir.join("repo").to_string_lossy().to_string()];
+        config.bin_dir = dir.join("bin");
+        config.kiss_compress = String::from("gz");
+
+        let cached = |pkg: &str| dir.join("bin").join(format!("{}@1.0-1.tar.gz", pkg));
+        let unverified = |pkg: &str| {
+            dir.join("bin")
+                .join(format!("{}@1.0-1.tar.gz.unverified", pkg))
+        };
+
+        // verified tarballs are moved into the binary cache
+        assert_eq!(
+            pkg_binrepo_fetch(&config, "good", "1.0-1").unwrap(),
+            Some(cached("good").to_string_lossy().to_string())
+        );
+        assert_eq!(fs::read_to_string(cached("good")).unwrap(), "good");
+        assert!(!unverified("good").exists());
+
+        // a wrong or too short checksum is an error and nothing is kept
+        for pkg in ["bad", "short"] {
+            match pkg_binrepo_fetch(&config, pkg, "1.0-1") {
+                Err(KissError::ChecksumMismatch { pkg: name, .. }) => assert_eq!(name, pkg),
+                _ => panic!("expected a checksum mismatch for {}", pkg),
+            }
+            assert!(!cached(pkg).exists());
+            assert!(!unverified(pkg).exists());
+        }
+
+        // a package which is not in the index is built instead
+        assert_eq!(pkg_binrepo_fetch(&config, "good", "2.0-1").unwrap(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
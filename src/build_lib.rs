@@ -1,19 +1,22 @@
+use crate::binrepo_lib::pkg_cache_or_fetch;
 use crate::checksum_lib::{get_file_hash, pkg_verify};
 use crate::install::pkg_install;
 use crate::manifest_lib::pkg_manifest;
 use crate::search_lib::{pkg_cache, pkg_find_path};
-use crate::source_lib::{pkg_source, pkg_source_resolve, pkg_source_tar, pkg_tar, SourceType};
+use crate::source_lib::{
+    archive_kind, compress_level_for, compression_for, pkg_source_all, pkg_source_missing,
+    pkg_source_resolve, pkg_source_tar, pkg_tar, SourceType,
+};
 
 use crate::shared_lib::{
     copy_folder, get_current_working_dir, get_directory_name,
     globals::{Config, Dependencies},
     mkcd, pkg_get_provides, prompt, read_a_files_lines, read_sources, remove_chars_after_last,
-    run_command, set_env_variable_if_undefined,
+    run_command, set_env_variable_if_undefined, KissError, Source,
 };
 
 // logging
-use crate::shared_lib::signal::pkg_clean;
-use crate::{die, log};
+use crate::log;
 
 // std
 use std::{
@@ -21,59 +24,61 @@ use std::{
     io::{self, BufRead, Read, Write},
     os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process::{Command, ExitStatus, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use nix::unistd::{chown, setgid, setuid, Gid, Uid, User};
 
-pub fn pkg_extract(config: &Config, pkg: &str, repo_dir: &String) {
+pub fn pkg_extract(config: &Config, pkg: &str, repo_dir: &String) -> Result<(), KissError> {
     if config.debug || config.verbose {
         log!(pkg, "Extracting sources");
     }
 
     let sources_file: String = format!("{}/sources", repo_dir);
 
-    let sources: Vec<(String, String)> =
-        read_sources(sources_file.as_str()).expect("Failed to read sources file");
+    let sources: Vec<Source> = read_sources(sources_file.as_str())?;
 
-    for (source, dest) in sources.iter() {
-        let source_type: SourceType =
-            pkg_source_resolve(config, pkg, repo_dir, source, dest, false);
+    for source in sources.iter() {
+        let source_type: SourceType = pkg_source_resolve(config, pkg, repo_dir, source, false)?;
         // temporary solution - need to find a better way
         let dest_path: PathBuf = config.mak_dir.join(pkg);
 
         // Create the source's directories.
-        mkcd(dest_path.to_string_lossy().to_string());
+        mkcd(dest_path.to_string_lossy().to_string())?;
 
         match source_type {
             SourceType::Git {
                 source: _,
                 destination,
             } => {
-                let dest_path = dest_path.join(dest);
-                copy_folder(destination.as_path(), dest_path.as_path())
-                    .expect("Failed to copy git source");
+                let dest_path = dest_path.join(&source.destination);
+                copy_folder(destination.as_path(), dest_path.as_path())?;
             }
             SourceType::Cached(destination) => {
-                if destination.contains("?no-extract") {
-                    let file_name = Path::new(&destination).file_name().unwrap();
-                    let dest_path: PathBuf = dest_path.join(file_name);
-                    fs::copy(destination, &dest_path).expect("Failed to copy file");
-                } else if destination.contains(".tar.") {
-                    let dest_path: PathBuf = dest_path.join(dest);
-                    if let Err(err) = fs::create_dir_all(&dest_path) {
-                        die!("Failed to create folder:", err);
-                    }
-                    pkg_source_tar(&destination, &dest_path, true);
+                let source_path: &Path = Path::new(&destination);
+                if !source.no_extract
+                    && source_path.is_file()
+                    && archive_kind(source_path)?.is_some()
+                {
+                    let dest_path: PathBuf = dest_path.join(&source.destination);
+                    fs::create_dir_all(&dest_path)?;
+                    pkg_source_tar(&destination, &dest_path, true, false)?;
                 } else {
-                    let file_name = Path::new(&destination).file_name().unwrap();
+                    let file_name = Path::new(&destination).file_name().ok_or_else(|| {
+                        KissError::Other(format!("Invalid source path '{}'", destination))
+                    })?;
                     let dest_path: PathBuf = dest_path.join(file_name);
-                    fs::copy(destination, &dest_path).expect("Failed to copy file");
+                    fs::copy(&destination, &dest_path)?;
                 }
             }
             _ => {}
         }
     }
+
+    Ok(())
 }
 
 // required for stripping
@@ -92,35 +97,30 @@ fn is_matching_directory(path: &Path) -> bool {
 }
 
 // for stripping
-fn strip_files_recursive(directory: &Path) {
-    let entries = fs::read_dir(directory).expect("Failed to read directory");
+fn strip_files_recursive(directory: &Path) -> Result<(), KissError> {
+    let entries = fs::read_dir(directory)?;
 
     let lib_and_exec_args: Vec<&str> = vec!["-s", "-R", ".comment", "-R", ".note"];
     let object_and_static_lib_args: Vec<&str> = vec!["-g", "-R", ".comment", "-R", ".note"];
 
     for entry in entries {
-        let entry = entry.unwrap();
-        let file_path: PathBuf = entry.path();
+        let file_path: PathBuf = entry?.path();
         let file_path_string: String = file_path.to_string_lossy().to_string();
 
         if file_path.is_dir() {
-            strip_files_recursive(&file_path);
+            strip_files_recursive(&file_path)?;
         } else if file_path.is_file() {
             if let Some(extension) = file_path.extension() {
                 if let Some(extension_str) = extension.to_str() {
                     if extension_str == "o" || extension_str == "a" {
                         let mut args: Vec<&str> = object_and_static_lib_args.clone();
                         args.push(&file_path_string);
-                        if let Err(err) = run_command("strip", &args) {
-                            die!("Failed to strip file:", file_path_string, "-", err);
-                        }
+                        strip_file(&file_path_string, &args)?;
                     } else if extension_str.contains("lib") {
                         // assume its a library
                         let mut args: Vec<&str> = lib_and_exec_args.clone();
                         args.push(&file_path_string);
-                        if let Err(err) = run_command("strip", &args) {
-                            die!("Failed to strip file:", file_path_string, "-", err);
-                        }
+                        strip_file(&file_path_string, &args)?;
                     }
                 }
             }
@@ -128,32 +128,35 @@ fn strip_files_recursive(directory: &Path) {
             else {
                 // to detect if it is a elf executable
                 let mut header = [0u8; 4];
-                if File::open(file_path)
-                    .expect("Failed to open file")
-                    .read_exact(&mut header)
-                    .is_err()
-                {
-                    return;
+                if File::open(file_path)?.read_exact(&mut header).is_err() {
+                    return Ok(());
                 }
 
                 if header == [0x7f, 0x45, 0x4c, 0x46] {
                     // assume it is a executable
                     let mut args: Vec<&str> = lib_and_exec_args.clone();
                     args.push(&file_path_string);
-                    if let Err(err) = run_command("strip", &args) {
-                        die!("Failed to strip file:", file_path_string, "-", err);
-                    }
+                    strip_file(&file_path_string, &args)?;
                 }
             }
         }
     }
+
+    Ok(())
 }
 
-fn pkg_strip(config: &Config, pkg: &str) {
+fn strip_file(file: &str, args: &Vec<&str>) -> Result<(), KissError> {
+    run_command("strip", args)
+        .map_err(|err| KissError::Other(format!("Failed to strip file: {} - {}", file, err)))?;
+
+    Ok(())
+}
+
+fn pkg_strip(config: &Config, pkg: &str) -> Result<(), KissError> {
     // Strip package binaries and libraries. This saves space on the system as
     // well as on the tarballs we ship for installation.
     if config.mak_dir.join(pkg).join("nostrip").exists() || !config.strip {
-        return;
+        return Ok(());
     }
 
     log!(pkg, "Stripping binaries and libraries");
@@ -164,7 +167,7 @@ fn pkg_strip(config: &Config, pkg: &str) {
         config.pkg_db,
         package_name = pkg
     );
-    let files = read_a_files_lines(manifest.as_str()).expect("Failed to read manifest");
+    let files = read_a_files_lines(manifest.as_str())?;
 
     for file in files {
         let real_file =
@@ -172,12 +175,14 @@ fn pkg_strip(config: &Config, pkg: &str) {
         let real_file_path = Path::new(real_file.as_str());
 
         if real_file_path.is_dir() && is_matching_directory(real_file_path) {
-            strip_files_recursive(real_file_path);
+            strip_files_recursive(real_file_path)?;
         }
     }
+
+    Ok(())
 }
 
-fn pkg_etcsums(config: &Config, pkg: &str) {
+fn pkg_etcsums(config: &Config, pkg: &str) -> Result<(), KissError> {
     // Generate checksums for each configuration file in the package's /etc/
     // directory for use in "smart" handling of these files.
     if config.debug {
@@ -186,7 +191,7 @@ fn pkg_etcsums(config: &Config, pkg: &str) {
 
     // Minor optimization - skip packages without /etc/.
     if !config.pkg_dir.join(pkg).join("etc").is_dir() {
-        return;
+        return Ok(());
     }
 
     let pkg_db_path: String = format!(
@@ -197,14 +202,14 @@ fn pkg_etcsums(config: &Config, pkg: &str) {
     );
 
     let manifest: String = format!("{}/manifest", pkg_db_path);
-    let manifest_file: File = File::open(manifest).expect("Failed to open manifest file");
+    let manifest_file: File = File::open(manifest)?;
     let manifest_reader = io::BufReader::new(manifest_file);
 
     // store etc files in this vector
     let mut etc_files: Vec<String> = Vec::new();
 
     for line in manifest_reader.lines() {
-        let etc: String = line.unwrap();
+        let etc: String = line?;
 
         if etc.starts_with("/etc") && !etc.ends_with('/') {
             if let Some(etc_file) = etc.strip_prefix('/') {
@@ -213,12 +218,7 @@ fn pkg_etcsums(config: &Config, pkg: &str) {
                 let etc_path = Path::new(&etc);
 
                 // Check if the path is a symbolic link
-                if etc_path
-                    .symlink_metadata()
-                    .unwrap()
-                    .file_type()
-                    .is_symlink()
-                {
+                if etc_path.symlink_metadata()?.file_type().is_symlink() {
                     etc_files.push(String::from("/dev/null"));
                 } else {
                     etc_files.push(etc);
@@ -228,44 +228,62 @@ fn pkg_etcsums(config: &Config, pkg: &str) {
     }
 
     let etcsums_path: String = format!("{}/etcsums", pkg_db_path);
-    let mut etcsums_file = File::create(etcsums_path).expect("Failed to create etcsums file");
+    let mut etcsums_file = File::create(etcsums_path)?;
 
     for etc_file in etc_files {
-        let hash = get_file_hash(etc_file.as_str()).expect("Failed to get file hash");
-        etcsums_file
-            .write_all(hash.as_bytes())
-            .expect("Failed to write hash to etcsums file");
+        let hash = get_file_hash(etc_file.as_str())?;
+        writeln!(etcsums_file, "{}", hash)?;
     }
+
+    Ok(())
 }
 
 // the method we use to store deps and explicit deps is different from original kiss pm.
 // we only store implicit deps in DEPS global var and explicit deps in EXPLICIT global var
+// stack holds the packages currently being resolved and is used to detect cycles
 #[inline(always)]
 fn pkg_depends(
     config: &Config,
     dependencies: &mut Dependencies,
+    stack: &mut Vec<String>,
     pkg: &String,
     expl: bool,
     filter: bool,
     dep_type: Option<&str>,
-) {
+) -> Result<(), KissError> {
     // check if user defined a replacement
     let pkg: &String = &pkg_get_provides(pkg, &config.provides_db).unwrap_or(pkg.to_owned());
     // since pkg_find function sets REPO_DIR and REPO_NAME, run it first
-    let repo_dir: PathBuf = pkg_find_path(config, pkg, None).unwrap_or(PathBuf::new());
+    let repo_dir: PathBuf = pkg_find_path(config, pkg, None).unwrap_or_default();
 
     // Resolve all dependencies and generate an ordered list. The deepest
     // dependencies are listed first and then the parents in reverse order.
     if dependencies.normal.contains(pkg) {
-        return;
+        return Ok(());
     }
 
     if !filter || dependencies.explicit.contains(pkg) || !expl && config.sys_db.join(pkg).exists() {
-        return;
+        return Ok(());
     }
 
-    if !repo_dir.exists() || repo_dir.join("depends").exists() {
-        let depends: Vec<String> = read_a_files_lines(repo_dir.join("depends")).unwrap();
+    // A package which is still being resolved depends on itself.
+    if let Some(index) = stack.iter().position(|x| x == pkg) {
+        let cycle: Vec<String> = stack[index..]
+            .iter()
+            .chain(std::iter::once(pkg))
+            .cloned()
+            .collect();
+        return Err(KissError::CircularDependency(cycle));
+    }
+
+    if !repo_dir.exists() {
+        return Err(KissError::NotFound(pkg.to_owned()));
+    }
+
+    // packages without a depends file are still added below
+    if repo_dir.join("depends").exists() {
+        let depends: Vec<String> = read_a_files_lines(repo_dir.join("depends"))?;
+        stack.push(pkg.to_owned());
         for dependency in depends {
             if dependency.starts_with('#') {
                 continue;
@@ -278,32 +296,65 @@ fn pkg_depends(
                 (dependency, None)
             };
 
-            pkg_depends(config, dependencies, &dep, false, filter, dependency_type);
+            pkg_depends(
+                config,
+                dependencies,
+                stack,
+                &dep,
+                false,
+                filter,
+                dependency_type,
+            )?;
         }
-    } else {
-        return;
+        stack.pop();
     }
 
     // add to dependency vec
-    if !expl || dep_type.unwrap_or("") == "make" && pkg_cache(config, pkg).is_none() {
+    if !expl || dep_type.unwrap_or("") == "make" && pkg_cache(config, pkg)?.is_none() {
         dependencies.normal.push(pkg.to_owned());
     }
+
+    Ok(())
 }
 
-pub fn pkg_build_all<T>(config: &Config, dependencies: &mut Dependencies, packages: Vec<T>)
+pub fn pkg_build_all<T>(
+    config: &Config,
+    dependencies: &mut Dependencies,
+    packages: Vec<T>,
+) -> Result<(), KissError>
 where
     T: AsRef<str> + std::clone::Clone + std::fmt::Display,
 {
+    // fail before building anything when the tarballs can't be created
+    compression_for(&config.kiss_compress)?;
+    compress_level_for(&config.kiss_compress, config.compress_level)?;
+
     // find dependencies
     if !packages.is_empty() {
         for package in packages {
-            pkg_depends(config, dependencies, &package.to_string(), true, true, None);
+            pkg_depends(
+                config,
+                dependencies,
+                &mut Vec::new(),
+                &package.to_string(),
+                true,
+                true,
+                None,
+            )?;
             dependencies.explicit.push(package.to_string());
         }
     } else {
         let current_dir: String = get_current_working_dir();
         let package: &str = get_directory_name(&current_dir);
-        pkg_depends(config, dependencies, &package.to_owned(), true, true, None);
+        pkg_depends(
+            config,
+            dependencies,
+            &mut Vec::new(),
+            &package.to_owned(),
+            true,
+            true,
+            None,
+        )?;
         dependencies.explicit.push(package.to_owned());
     }
 
@@ -326,6 +377,20 @@ where
         )
     }
 
+    // in offline mode list every missing source of the packages which have to
+    // be built before prompting or installing anything
+    if config.offline {
+        let mut build_set: Vec<&String> = Vec::new();
+        for pkg in dependencies.normal.iter() {
+            if pkg_cache(config, pkg)?.is_none() {
+                build_set.push(pkg);
+            }
+        }
+        build_set.extend(dependencies.explicit.iter());
+
+        pkg_source_missing(config, &build_set)?;
+    }
+
     // prompt
     if !dependencies.normal.is_empty() && config.prompt {
         prompt(None);
@@ -335,14 +400,12 @@ where
         println!("Checking for pre-built dependencies");
     }
     // Install any pre-built dependencies if they exist in the binary
-    // directory and are up to date.
+    // directory or KISS_BINREPO and are up to date.
     for pkg in dependencies.normal.clone() {
-        if pkg_cache(config, &pkg).is_some() {
+        if pkg_cache_or_fetch(config, &pkg)?.is_some() {
             log!(pkg, "Found pre-built binary");
             dependencies.normal.retain(|x| x != &pkg);
-            if let Err(err) = pkg_install(config, &pkg) {
-                die!("Failed to install package:", pkg, err);
-            }
+            pkg_install(config, &pkg)?;
         }
     }
 
@@ -353,15 +416,16 @@ where
         .collect();
 
     // download and check sources
+    pkg_source_all(config, &all_packages, false, true)?;
+
     for package in &all_packages {
-        pkg_source(config, package, false, true);
         let repo_dir = pkg_find_path(config, package, None)
-            .unwrap_or_else(|| die!(package, "Failed to get version"))
+            .ok_or_else(|| KissError::NotFound(package.to_string()))?
             .to_string_lossy()
             .to_string();
 
         if Path::new(&repo_dir).join("sources").exists() {
-            pkg_verify(config, package, &repo_dir);
+            pkg_verify(config, package, &repo_dir)?;
         }
     }
 
@@ -376,29 +440,27 @@ where
         log!(package, build_status);
 
         let repo_dir: String = pkg_find_path(config, package, None)
-            .unwrap_or_else(|| die!(package, "Failed to get version"))
+            .ok_or_else(|| KissError::NotFound(package.to_string()))?
             .to_string_lossy()
             .to_string();
 
         if Path::new(repo_dir.as_str()).join("sources").exists() {
-            pkg_extract(config, package, &repo_dir);
+            pkg_extract(config, package, &repo_dir)?;
         }
 
-        pkg_build(config, package, &repo_dir);
-        pkg_manifest(config, package, &config.pkg_dir);
-        pkg_strip(config, package);
+        pkg_build(config, package, &repo_dir)?;
+        pkg_manifest(config, package, &config.pkg_dir)?;
+        pkg_strip(config, package)?;
 
-        pkg_etcsums(config, package);
-        pkg_tar(config, package);
+        pkg_etcsums(config, package)?;
+        pkg_tar(config, package)?;
 
         if !dependencies.explicit.contains(package) {
             log!(
                 package,
                 "Needed as a dependency or has an update, installing"
             );
-            if let Err(err) = pkg_install(config, package) {
-                die!("Failed to install package:", package, err);
-            }
+            pkg_install(config, package)?;
         }
     }
 
@@ -408,15 +470,15 @@ where
             dependencies.explicit.join(" ")
         )));
         for pkg in &dependencies.explicit {
-            if let Err(err) = pkg_install(config, pkg) {
-                die!("Failed to install: {}", err);
-            }
+            pkg_install(config, pkg)?;
         }
     }
+
+    Ok(())
 }
 
-fn pkg_build(config: &Config, pkg: &str, repo_dir: &String) {
-    mkcd(format!("{}/{}", config.mak_dir.to_string_lossy(), pkg).as_str());
+fn pkg_build(config: &Config, pkg: &str, repo_dir: &String) -> Result<(), KissError> {
+    mkcd(format!("{}/{}", config.mak_dir.to_string_lossy(), pkg).as_str())?;
 
     log!(pkg, "Starting build");
 
@@ -429,64 +491,108 @@ fn pkg_build(config: &Config, pkg: &str, repo_dir: &String) {
     let executable: String = format!("{}/build", repo_dir);
     let install_dir: PathBuf = config.pkg_dir.join(pkg);
 
-    let user_info: User = match User::from_uid(1000.into()) {
-        Ok(Some(user)) => user,
-        Ok(None) => die!("Failed to find user: nobody"),
-        Err(err) => die!("Failed to get user info", err),
+    // Privileges can only be dropped when running as root, otherwise the
+    // build runs as the invoking user.
+    let user_info: Option<User> = if Uid::effective().is_root() {
+        Some(pkg_build_user(config)?)
+    } else {
+        None
     };
 
     // Recursively change the group ownership
-    if let Err(err) = change_group_recursive(&config.proc, user_info.uid, user_info.gid) {
-        die!("Error changing group recursively", err);
+    if let Some(user_info) = &user_info {
+        change_group_recursive(&config.proc, user_info.uid, user_info.gid)?;
     }
 
-    let path_variable: String = std::env::var("PATH").unwrap();
+    let path_variable: String = std::env::var("PATH").unwrap_or_default();
+
+    // Both stdout and stderr of the build script are written to this log,
+    // it is only kept for failed builds unless --keep-logs is set.
+    let timestamp: u64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let log_file_path: PathBuf = config
+        .log_dir
+        .join(format!("{}-{}-{}.log", pkg, timestamp, config.pid));
+    let log_file: Arc<Mutex<File>> = Arc::new(Mutex::new(File::create(&log_file_path)?));
 
     let mut child: Command = Command::new(executable);
     child
         .arg(install_dir.to_string_lossy().to_string())
-        .stdout(if config.quiet {
-            Stdio::null()
-        } else {
-            Stdio::inherit()
-        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .env("PATH", &path_variable);
 
-    unsafe {
-        child.pre_exec(move || {
-            // Set the UID and GID to "nobody" within the new user namespace
-            setgid(user_info.gid).map_err(|err| {
-                eprintln!("Error setting GID: {}", err);
-                err
-            })?;
-            setuid(user_info.uid).map_err(|err| {
-                eprintln!("Error setting UID: {}", err);
-                err
-            })?;
-
-            println!(
-                "Dropped root privileges. Running as user: {}",
-                user_info.name
-            );
-
-            Ok(())
-        });
+    if let Some(user_info) = user_info {
+        unsafe {
+            child.pre_exec(move || {
+                // Set the UID and GID to the build user
+                setgid(user_info.gid).inspect_err(|err| {
+                    eprintln!("Error setting GID: {}", err);
+                })?;
+                setuid(user_info.uid).inspect_err(|err| {
+                    eprintln!("Error setting UID: {}", err);
+                })?;
+
+                println!(
+                    "Dropped root privileges. Running as user: {}",
+                    user_info.name
+                );
+
+                Ok(())
+            });
+        }
     }
 
+    // nothing was logged if the build script couldn't be started
+    let mut process: Child = match child.spawn() {
+        Ok(process) => process,
+        Err(err) => {
+            let _ = fs::remove_file(&log_file_path);
+            return Err(err.into());
+        }
+    };
+
+    let stdout_tee = process.stdout.take().map(|stdout| {
+        let terminal: Option<io::Stdout> = (!config.quiet).then(io::stdout);
+        tee_output(stdout, terminal, Arc::clone(&log_file))
+    });
+    let stderr_tee = process
+        .stderr
+        .take()
+        .map(|stderr| tee_output(stderr, Some(io::stderr()), Arc::clone(&log_file)));
+
     // wait for build to finish and return status
-    let status: ExitStatus = child.status().unwrap_or_else(|err| die!("Error", err));
+    let status: ExitStatus = process.wait()?;
+
+    // make sure everything the build printed made it into the log
+    for tee in [stdout_tee, stderr_tee].into_iter().flatten() {
+        let _ = tee.join();
+    }
+
     if status.success() {
         // give info
-        log!(pkg, "Successfully built package")
+        log!(pkg, "Successfully built package");
+
+        if config.keep_logs {
+            if config.debug || config.verbose {
+                log!(pkg, "Build log saved to", log_file_path.to_string_lossy());
+            }
+        } else if let Err(err) = fs::remove_file(&log_file_path) {
+            eprintln!("Failed to remove build log: {}", err);
+        }
     } else {
-        die!(pkg, "Build failed");
+        return Err(KissError::BuildFailed {
+            pkg: pkg.to_owned(),
+            log: log_file_path,
+        });
     }
 
     if config.debug {
-        println!(
-            "current user: {}",
-            User::from_uid(nix::unistd::getuid()).unwrap().unwrap().name
-        );
+        if let Ok(Some(user)) = User::from_uid(nix::unistd::getuid()) {
+            println!("current user: {}", user.name);
+        }
     }
 
     // Copy the repository files to the package directory.
@@ -496,9 +602,62 @@ fn pkg_build(config: &Config, pkg: &str, repo_dir: &String) {
         config.pkg_db,
         package_name = pkg
     );
-    mkcd(pkg_db_dir.as_str());
-    if let Err(err) = copy_folder(Path::new(&repo_dir), Path::new(pkg_db_dir.as_str())) {
-        die!("Failed to copy repository files", err);
+    mkcd(pkg_db_dir.as_str())?;
+    copy_folder(Path::new(&repo_dir), Path::new(pkg_db_dir.as_str()))?;
+
+    Ok(())
+}
+
+// copy everything a child process writes to the terminal and to the build log
+fn tee_output<R, W>(
+    mut reader: R,
+    mut terminal: Option<W>,
+    log_file: Arc<Mutex<File>>,
+) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buffer: [u8; 8192] = [0; 8192];
+
+        loop {
+            let bytes_read: usize = match reader.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(bytes_read) => bytes_read,
+            };
+
+            if let Some(terminal) = terminal.as_mut() {
+                let _ = terminal.write_all(&buffer[..bytes_read]);
+                let _ = terminal.flush();
+            }
+
+            if let Ok(mut log_file) = log_file.lock() {
+                let _ = log_file.write_all(&buffer[..bytes_read]);
+            }
+        }
+    })
+}
+
+// get the user build scripts are run as, config.build_user is either a name or a uid
+fn pkg_build_user(config: &Config) -> Result<User, KissError> {
+    let build_user: &str = config.build_user.as_str();
+
+    let user = match build_user.parse::<u32>() {
+        Ok(uid) => User::from_uid(uid.into()),
+        Err(_) => User::from_name(build_user),
+    };
+
+    match user {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(KissError::Other(format!(
+            "Failed to find build user '{}'",
+            build_user
+        ))),
+        Err(err) => Err(KissError::Other(format!(
+            "Failed to get user info: {}",
+            err
+        ))),
     }
 }
 
@@ -519,3 +678,77 @@ fn change_group_recursive(path: &Path, new_uid: Uid, new_gid: Gid) -> std::io::R
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // KISS_PATH with a repository of packages and their depends files
+    fn test_config(name: &str, packages: &[(&str, &str)]) -> Config {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (pkg, depends) in packages {
+            let repo_dir: PathBuf = dir.join("repo").join(pkg);
+            fs::create_dir_all(&repo_dir).unwrap();
+            fs::write(repo_dir.join("version"), "1.0 1\n").unwrap();
+            fs::write(repo_dir.join("depends"), depends).unwrap();
+        }
+
+        let mut config: Config = Config::new();
+        config.kiss_path = vec![dir.join("repo").to_string_lossy().to_string()];
+        config.sys_db = dir.join("installed");
+        config.provides_db = dir.join("provides");
+        config
+    }
+
+    #[test]
+    fn circular_dependency() {
+        let config: Config = test_config(
+            "circular-dependency",
+            &[("a", "b\n"), ("b", "c make\n"), ("c", "a\n")],
+        );
+
+        let result = pkg_depends(
+            &config,
+            &mut Dependencies::default(),
+            &mut Vec::new(),
+            &"a".to_owned(),
+            true,
+            true,
+            None,
+        );
+
+        match result {
+            Err(KissError::CircularDependency(cycle)) => assert_eq!(cycle, ["a", "b", "c", "a"]),
+            result => panic!("expected a circular dependency, got {:?}", result),
+        }
+
+        fs::remove_dir_all(config.sys_db.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn ordered_dependencies() {
+        let config: Config = test_config(
+            "ordered-dependencies",
+            &[("a", "b\nc\n"), ("b", "c\n"), ("c", "")],
+        );
+        let mut dependencies: Dependencies = Dependencies::default();
+
+        pkg_depends(
+            &config,
+            &mut dependencies,
+            &mut Vec::new(),
+            &"a".to_owned(),
+            true,
+            true,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(dependencies.normal, ["c", "b"]);
+
+        fs::remove_dir_all(config.sys_db.parent().unwrap()).unwrap();
+    }
+}
@@ -1,98 +1,166 @@
 use crate::search_lib::pkg_find_path;
-use crate::shared_lib::{get_directory_name, globals::Config, read_a_files_lines, read_sources};
+use crate::shared_lib::{
+    get_directory_name, globals::Config, read_a_files_lines, read_sources, KissError, Source,
+    SourceKind,
+};
 use crate::source_lib::{pkg_source, pkg_source_resolve, SourceType};
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufWriter, Read, Result, Write},
+    fs::OpenOptions,
+    io::{BufWriter, Write},
     path::{Path, PathBuf},
 };
 // logging
-use crate::shared_lib::signal::pkg_clean;
-use crate::{die, log};
+use crate::log;
 // for b3sum hash generation
 use blake3::Hasher;
 
 // threading
 use crate::iter;
 #[cfg(feature = "threading")]
-use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::iter::ParallelIterator;
+
+// checksums file entry for sources which should not be verified
+pub const SKIP: &str = "SKIP";
 
-pub fn pkg_checksum_gen(config: &Config, package_name: &str, repo_dir: &str) -> Vec<String> {
+// (source, hash) for every source which needs a checksum, in the order of
+// the sources file. git sources are not verified and get no entry.
+pub fn pkg_checksum_gen(
+    config: &Config,
+    package_name: &str,
+    repo_dir: &str,
+) -> Result<Vec<(String, String)>, KissError> {
     let sources_path: PathBuf = Path::new(repo_dir).join("sources");
-    let sources: Vec<(String, String)> = read_sources(sources_path.to_str().unwrap_or("sources"))
-        .expect("Failed to read sources file");
-
-    let hashes: Vec<_> = iter!(sources)
-        .filter_map(|(source, dest)| {
-            if !source.is_empty() && !source.starts_with("git+") {
-                let source_type =
-                    pkg_source_resolve(config, package_name, repo_dir, source, dest, false);
-
-                // if it is a local source
-                if let SourceType::Cached(des) = source_type {
-                    Some(get_file_hash(&des))
-                } else {
-                    None
-                }
-            } else {
-                None
+    let sources: Vec<Source> = read_sources(&sources_path)?;
+
+    let hashes: Vec<Option<(String, String)>> = iter!(sources)
+        .map(|source| {
+            if source.kind == SourceKind::Git {
+                return Ok(None);
+            }
+
+            match pkg_source_resolve(config, package_name, repo_dir, source, false)? {
+                SourceType::Cached(des) => Ok(Some((source.url.to_owned(), get_file_hash(&des)?))),
+                _ => Err(KissError::Other(format!(
+                    "{}: No local file: {}",
+                    package_name, source.url
+                ))),
             }
         })
-        .filter_map(|r| r.ok())
-        .collect();
+        .collect::<Result<Vec<Option<(String, String)>>, KissError>>()?;
 
-    hashes
+    Ok(hashes.into_iter().flatten().collect())
 }
 
-pub fn get_file_hash(file_path: &str) -> Result<String> {
-    let mut file = File::open(file_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+pub fn get_file_hash(file_path: &str) -> Result<String, std::io::Error> {
+    let mut hasher = Hasher::new();
 
-    let mut hash = Hasher::new().update(&buffer).finalize_xof();
+    // large files are memory mapped (and hashed on every core with threading),
+    // small ones and anything that can't be mapped are streamed in chunks
+    #[cfg(feature = "threading")]
+    hasher.update_mmap_rayon(file_path)?;
+    #[cfg(not(feature = "threading"))]
+    hasher.update_mmap(file_path)?;
 
-    let mut hash_output = vec![0; 33];
-    hash.fill(hash_output.as_mut_slice());
+    // checksums files store 33 bytes of XOF output
+    let mut hash_output = [0; 33];
+    hasher.finalize_xof().fill(&mut hash_output);
 
     Ok(faster_hex::hex_string(&hash_output))
 }
 
-pub fn pkg_verify(config: &Config, pkg: &str, repo_dir: &String) {
+pub fn pkg_verify(config: &Config, pkg: &str, repo_dir: &str) -> Result<(), KissError> {
     if config.debug || config.verbose {
         log!(pkg, "Verifying sources");
     }
 
-    let hashes: Vec<String> = pkg_checksum_gen(config, pkg, repo_dir.as_str());
-    let checksums: Vec<String> =
-        read_a_files_lines(format!("{}/checksums", repo_dir).as_str()).expect("No checksums file");
+    let hashes: Vec<(String, String)> = pkg_checksum_gen(config, pkg, repo_dir)?;
+    let checksums: Vec<String> = read_checksums(repo_dir)?;
 
-    iter!(hashes)
-        .zip(iter!(checksums))
-        .for_each(|(element1, element2)| {
-            if config.debug || config.verbose {
-                println!("- {}\n+ {}", element2, element1);
-            }
-            // checksum mismatch
-            if element1 != element2 {
-                die!(pkg, "Checksum mismatch");
-            }
+    if config.debug || config.verbose {
+        for ((source, actual), expected) in hashes.iter().zip(&checksums) {
+            println!("{}\n- {}\n+ {}", source, expected, actual);
+        }
+    }
+
+    compare_checksums(pkg, hashes, checksums)
+}
+
+// pair every (source, hash) with the checksum on the same position
+fn compare_checksums(
+    pkg: &str,
+    hashes: Vec<(String, String)>,
+    checksums: Vec<String>,
+) -> Result<(), KissError> {
+    // every source is paired with the checksum on the same position, a
+    // missing or extra line would shift all following pairs
+    if hashes.len() != checksums.len() {
+        return Err(KissError::ChecksumCount {
+            pkg: pkg.to_owned(),
+            sources: hashes.len(),
+            checksums: checksums.len(),
+        });
+    }
+
+    let mismatches: Vec<(String, String, String)> = hashes
+        .into_iter()
+        .zip(checksums)
+        .filter_map(|((source, actual), expected)| {
+            (expected != SKIP && expected != actual).then_some((source, expected, actual))
+        })
+        .collect();
+
+    if !mismatches.is_empty() {
+        return Err(KissError::ChecksumMismatch {
+            pkg: pkg.to_owned(),
+            mismatches,
         });
+    }
+
+    Ok(())
+}
+
+// lines of the checksums file, a package without one has no checksums
+fn read_checksums(repo_dir: &str) -> Result<Vec<String>, KissError> {
+    let checksums_path: PathBuf = Path::new(repo_dir).join("checksums");
+
+    if !checksums_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(
+        read_a_files_lines(checksums_path.to_string_lossy().as_ref())?
+            .into_iter()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect(),
+    )
 }
 
-pub fn pkg_checksum(config: &Config, package: &str) {
-    pkg_source(config, package, true, false);
+pub fn pkg_checksum(config: &Config, package: &str) -> Result<(), KissError> {
+    pkg_source(config, package, true, false)?;
 
     let repo_dir: String = pkg_find_path(config, package, None)
-        .unwrap_or_else(|| die!(package, "Failed to get version"))
+        .ok_or_else(|| KissError::NotFound(package.to_owned()))?
         .to_string_lossy()
         .to_string();
     let repo_name: &str = get_directory_name(&repo_dir);
 
     if !Path::new(repo_dir.as_str()).join("sources").exists() {
-        return;
+        return Ok(());
     }
 
-    let hashes: Vec<String> = pkg_checksum_gen(config, package, repo_dir.as_str());
+    let hashes: Vec<(String, String)> = pkg_checksum_gen(config, package, repo_dir.as_str())?;
+
+    // keep sources which were explicitly marked as SKIP unverified
+    let old_checksums: Vec<String> = read_checksums(&repo_dir).unwrap_or_default();
+    let hashes: Vec<String> = match old_checksums.len() == hashes.len() {
+        true => hashes
+            .into_iter()
+            .zip(old_checksums)
+            .map(|((_, hash), old)| if old == SKIP { old } else { hash })
+            .collect(),
+        false => hashes.into_iter().map(|(_, hash)| hash).collect(),
+    };
 
     if !hashes.is_empty() {
         // create or recreate checksums file
@@ -100,27 +168,138 @@ pub fn pkg_checksum(config: &Config, package: &str) {
             .write(true)
             .truncate(true)
             .create(true)
-            .open(format!("{}/checksums", repo_dir))
-            .expect("Failed to create or recreate checksums file");
+            .open(format!("{}/checksums", repo_dir))?;
 
         // use a buffered writer for performance
         let mut writer = BufWriter::new(checksums_file);
 
         for hash in hashes {
             println!("{}", hash);
-            writer
-                .write_all(hash.as_bytes())
-                .expect("Failed to write to checksums file");
-            writer
-                .write_all(b"\n")
-                .expect("Failed to write to checksums file");
+            writer.write_all(hash.as_bytes())?;
+            writer.write_all(b"\n")?;
         }
 
         // ensure all data is written to the file
-        writer.flush().expect("Failed to write to checksums file");
+        writer.flush()?;
 
         log!(repo_name, "Generated checksums");
     } else {
         log!(repo_name, "No sources needing checksums");
     }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(hashes: &[(&str, &str)]) -> Vec<(String, String)> {
+        hashes
+            .iter()
+            .map(|(source, hash)| (source.to_string(), hash.to_string()))
+            .collect()
+    }
+
+    fn checksums(checksums: &[&str]) -> Vec<String> {
+        checksums.iter().map(|hash| hash.to_string()).collect()
+    }
+
+    #[test]
+    fn matching_checksums() {
+        let result = compare_checksums(
+            "foo",
+            hashes(&[("a.tar.gz", "aa"), ("b.patch", "bb")]),
+            checksums(&["aa", "bb"]),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn skipped_checksums() {
+        let result = compare_checksums(
+            "foo",
+            hashes(&[("a.tar.gz", "aa"), ("b.patch", "bb")]),
+            checksums(&[SKIP, "bb"]),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn mismatched_checksum() {
+        let result = compare_checksums(
+            "foo",
+            hashes(&[("a.tar.gz", "aa"), ("b.patch", "bb")]),
+            checksums(&["aa", "cc"]),
+        );
+        match result {
+            Err(KissError::ChecksumMismatch { pkg, mismatches }) => {
+                assert_eq!(pkg, "foo");
+                assert_eq!(
+                    mismatches,
+                    vec![("b.patch".to_owned(), "cc".to_owned(), "bb".to_owned())]
+                );
+            }
+            _ => panic!("expected a checksum mismatch"),
+        }
+    }
+
+    #[test]
+    fn missing_and_extra_checksums() {
+        for lines in [&["aa"][..], &["aa", "bb", "cc"][..]] {
+            let result = compare_checksums(
+                "foo",
+                hashes(&[("a.tar.gz", "aa"), ("b.patch", "bb")]),
+                checksums(lines),
+            );
+            match result {
+                Err(KissError::ChecksumCount {
+                    sources, checksums, ..
+                }) => {
+                    assert_eq!(sources, 2);
+                    assert_eq!(checksums, lines.len());
+                }
+                _ => panic!("expected a checksum count error"),
+            }
+        }
+    }
+
+    #[test]
+    fn file_hashes() {
+        let dir =
+            std::env::temp_dir().join(format!("kiss-test-{}-file-hashes", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the large fixture is past blake3's mmap threshold, the others are
+        // read through the fallback
+        let large: Vec<u8> = (0..1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let fixtures: [(&str, &[u8], &str); 3] = [
+            (
+                "empty",
+                b"",
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262e0",
+            ),
+            (
+                "small",
+                b"kiss\n",
+                "bd7a59e05d3fac9e5f335577a8e6f98bbbbadc2fd94cbe732b37ab7dc3e1958bcd",
+            ),
+            (
+                "large",
+                &large,
+                "74cb441fd087764ca9c3694da742ebe30cbeb3060a17009ca81825c7a8d1034359",
+            ),
+        ];
+
+        for (name, content, expected) in fixtures {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            let hash = get_file_hash(path.to_str().unwrap()).unwrap();
+            assert_eq!(hash.len(), 66);
+            assert_eq!(hash, expected, "{}", name);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
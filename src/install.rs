@@ -1,96 +1,96 @@
+use crate::alternatives_lib::path_to_choice;
+use crate::binrepo_lib::pkg_cache_or_fetch;
 use crate::checksum_lib::get_file_hash;
 use crate::manifest_lib::{pkg_manifest, pkg_manifest_validate};
-use crate::search_lib::pkg_cache;
+use crate::owns_lib::{update_owners_cache, OwnerIndex};
 use crate::shared_lib::{
     cat, globals::Config, is_symlink, mkcd, pkg_get_provides, read_a_dir_and_sort,
-    read_a_files_lines, remove_chars_after_last, resolve_path,
+    read_a_files_lines, resolve_path, KissError,
 };
 use crate::source_lib::pkg_source_tar;
 
 // logging
-use crate::shared_lib::signal::pkg_clean;
-use crate::{die, log};
+use crate::log;
 
 use std::{
     ffi::OsStr,
-    fs::{self, File},
-    io::{BufRead, BufReader},
-    os::unix::fs::PermissionsExt,
+    fs,
+    os::unix::fs::{lchown, MetadataExt, PermissionsExt},
     path::{Path, PathBuf},
 };
 
 // threading
-use crate::{iter, sort_reverse};
+use crate::{iter, sort, sort_reverse};
 #[cfg(feature = "threading")]
 use rayon::iter::ParallelIterator;
 
-// TODO: improve performance - it must be slow atm
 fn pkg_conflicts(
     config: &Config,
     pkg: &str,
     manifest_file_path: &PathBuf,
     choice: bool,
     debug: bool,
-) -> Result<(), std::io::Error> {
+) -> Result<(), KissError> {
     if debug {
         log!(pkg, "Checking for package conflicts");
     }
 
-    let mut conflicts: Vec<String> = Vec::new();
+    let root: PathBuf = fs::canonicalize(&config.kiss_root).unwrap_or(config.kiss_root.clone());
 
+    // (path in the package manifest, path with symlinks resolved)
     let manifest_contents: Vec<String> = read_a_files_lines(manifest_file_path)?;
-    let resolved_paths: Vec<String> = manifest_contents
+    let resolved_paths: Vec<(String, String)> = manifest_contents
         .into_iter()
         .filter(|line| !line.ends_with('/'))
         .filter_map(|line| {
-            resolve_path(config, line.as_str()).map(|path| path.to_string_lossy().to_string())
+            let resolved: PathBuf = resolve_path(config, line.as_str())?;
+            let relative: &Path = resolved.strip_prefix(&root).ok()?;
+            let resolved: String = format!("/{}", relative.to_string_lossy());
+            Some((line, resolved))
         })
         .collect();
-    dbg!(&resolved_paths);
 
-    // only get manifest files
-    let sys_manifest_files: Vec<PathBuf> = iter!(read_a_dir_and_sort(
-        config.sys_db.to_string_lossy().to_string(),
-        true,
-        &[]
-    ))
-    .filter(|file| {
-        !file.to_string_lossy().to_string().contains(&format!(
-            "{}/{}",
-            config.sys_db.to_string_lossy(),
-            pkg
-        )) && file.file_name().unwrap().to_str() == Some("manifest")
-    })
-    .map(|name| name.to_path_buf())
-    .collect();
-
-    let mut safe: bool = false;
-
-    for sys_manifest_path in sys_manifest_files {
-        let sys_manifest_file: File = fs::File::open(sys_manifest_path).unwrap();
-        let sys_manifest_reader: BufReader<File> = BufReader::new(sys_manifest_file);
-
-        for line in sys_manifest_reader.lines().flatten() {
-            let found: bool = iter!(resolved_paths).any(|path| path == &line);
-            if found {
-                conflicts.push(line);
-                break;
-            }
-        }
+    let index: OwnerIndex = OwnerIndex::new(config)?;
 
-        if !conflicts.is_empty() {
-            break;
-        }
+    // (path in the package manifest, path on the system, owner)
+    let conflicts: Vec<(String, String, String)> = resolved_paths
+        .into_iter()
+        .filter_map(|(path, resolved)| {
+            let owner: String = index
+                .owner(&path, Some(pkg))
+                .or_else(|| index.owner(&resolved, Some(pkg)))?
+                .to_owned();
+            Some((path, resolved, owner))
+        })
+        .collect();
+
+    if conflicts.is_empty() {
+        return Ok(());
     }
 
-    // TODO: Enable alternatives automatically if it is safe to do so.
+    // Enable alternatives automatically if it is safe to do so.
     // This checks to see that the package that is about to be installed
-    // doesn't overwrite anything it shouldn't in '/var/db/kiss/installed'.
-    if !conflicts.is_empty() {
-        safe = true;
-    }
+    // doesn't overwrite anything it shouldn't in '/var/db/kiss/installed',
+    // that none of the conflicts are directories and that none of them are
+    // owned by the currently installed version of this package.
+    let pkg_db_prefix: String = format!("/{}/", config.pkg_db);
+
+    let unsafe_conflicts: Vec<&(String, String, String)> = conflicts
+        .iter()
+        .filter(|(path, resolved, _)| {
+            let sys_path: PathBuf = config.kiss_root.join(resolved.trim_start_matches('/'));
+            let tar_path: PathBuf = config.tar_dir.join(pkg).join(path.trim_start_matches('/'));
+
+            path.starts_with(&pkg_db_prefix)
+                || resolved.starts_with(&pkg_db_prefix)
+                || (sys_path.is_dir() && !is_symlink(&sys_path))
+                || (tar_path.is_dir() && !is_symlink(&tar_path))
+                || index.owners(path).iter().any(|owner| owner == pkg)
+                || index.owners(resolved).iter().any(|owner| owner == pkg)
+        })
+        .collect();
 
-    if choice && safe && !conflicts.is_empty() {
+    if choice && unsafe_conflicts.is_empty() {
         // Handle conflicts and create choices
         let choice_directory: PathBuf = config.tar_dir.join(pkg).join(&config.cho_db);
         // Create the "choices" directory inside of the tarball.
@@ -99,16 +99,12 @@ fn pkg_conflicts(
 
         let mut choices_created: usize = 0;
 
-        for conflict in conflicts {
-            println!("Found conflict: {}", conflict);
+        for (path, _, owner) in conflicts {
+            println!("Found conflict: {} ({})", path, owner);
 
-            let new_file_name: String = format!(
-                "{}>{}",
-                pkg,
-                conflict.trim_start_matches('/').replace('/', ">")
-            );
-            let choice_file_path: PathBuf = choice_directory.join(new_file_name);
-            let real_conflict_path: PathBuf = config.tar_dir.join(pkg).join(conflict);
+            let choice_file_path: PathBuf = choice_directory.join(path_to_choice(pkg, &path));
+            let real_conflict_path: PathBuf =
+                config.tar_dir.join(pkg).join(path.trim_start_matches('/'));
 
             fs::rename(real_conflict_path, choice_file_path)?;
             choices_created += 1;
@@ -118,56 +114,75 @@ fn pkg_conflicts(
             log!(pkg, "Converted all conflicts to choices (kiss a)");
             // Rewrite the package's manifest to update its location
             // to its new spot (and name) in the choices directory.
-            pkg_manifest(config, pkg, &config.tar_dir);
+            pkg_manifest(config, pkg, &config.tar_dir)?;
         }
-    } else if !conflicts.is_empty() {
-        println!("Package '{}' conflicts with another package !>", pkg);
-        println!("Run 'KISS_CHOICE=1 kiss i '{}' to add conflicts !>", pkg);
-        die!("", "as alternatives. !>");
+    } else {
+        let safe: bool = unsafe_conflicts.is_empty();
+        let conflicts: Vec<(String, String)> = match safe {
+            true => conflicts
+                .into_iter()
+                .map(|(path, _, owner)| (path, owner))
+                .collect(),
+            false => unsafe_conflicts
+                .into_iter()
+                .map(|(path, _, owner)| (path.to_owned(), owner.to_owned()))
+                .collect(),
+        };
+
+        return Err(KissError::Conflict {
+            pkg: pkg.to_owned(),
+            conflicts,
+            safe,
+        });
     }
 
     Ok(())
 }
 
-fn pkg_installable(config: &Config, pkg: &str, depends_file_path: &String) {
+fn pkg_installable(
+    config: &Config,
+    pkg: &str,
+    depends_file_path: &String,
+) -> Result<(), KissError> {
     if config.debug || config.verbose {
         log!(pkg, "Checking if package installable");
     }
 
-    let mut count: usize = 0;
+    let mut missing: Vec<String> = Vec::new();
 
-    let depends: Vec<String> = read_a_files_lines(depends_file_path).unwrap();
+    // a package without dependencies has no depends file
+    let depends: Vec<String> = read_a_files_lines(depends_file_path).unwrap_or_default();
 
     for dependency in depends {
         if dependency.starts_with('#') {
             continue;
         }
 
-        let (dep, dependency_type): (String, Option<&str>) = if dependency.contains(" make") {
-            let binding = &remove_chars_after_last(&dependency, ' ').trim_end();
-            (binding.to_string(), Some("make"))
-        } else {
-            (dependency, None)
-        };
-
-        // check if user defined a replacement
-        let pkg: &String = &pkg_get_provides(pkg, &config.provides_db).unwrap_or(pkg.to_owned());
-        if &dep != pkg {
+        // make dependencies are only needed to build the package
+        if dependency.contains(" make") {
             continue;
         }
-
-        if config.sys_db.join(&dep).exists() {
+        let dep: String = dependency.trim().to_owned();
+        if dep.is_empty() {
             continue;
         }
 
-        println!("{} {}", dep, dependency_type.unwrap_or(""));
+        // check if user defined a replacement
+        let dep: String = pkg_get_provides(&dep, &config.provides_db).unwrap_or(dep);
 
-        count += 1;
+        if !config.sys_db.join(&dep).exists() {
+            missing.push(dep);
+        }
     }
 
-    if count != 0 {
-        die!(pkg, "Package not installable, missing", count, "package(s)");
+    if !missing.is_empty() {
+        return Err(KissError::NotInstallable {
+            pkg: pkg.to_owned(),
+            missing,
+        });
     }
+
+    Ok(())
 }
 
 // TODO: fix sum_old
@@ -179,16 +194,11 @@ fn pkg_etc(dest_path: &Path, file: &String, new_file: String, verify: bool) -> b
     };
 
     // sum of file in the system
-    let sum_sys: String = match get_file_hash(dest_path.to_string_lossy().to_string().as_str()) {
-        Ok(hash) => hash,
-        _ => String::new(),
-    };
+    let sum_sys: String =
+        get_file_hash(dest_path.to_string_lossy().to_string().as_str()).unwrap_or_default();
 
     // sum of file that will be installed
-    let sum_new: String = match get_file_hash(&new_file) {
-        Ok(hash) => hash,
-        _ => String::new(),
-    };
+    let sum_new: String = get_file_hash(&new_file).unwrap_or_default();
 
     if let Some(sum_old) = sum_old {
         // old = Y, sys = X, new = Y
@@ -252,6 +262,7 @@ fn pkg_install_files(
             // Set permissions for the directory.
             let permissions = fs::Permissions::from_mode(octal_permissions);
             fs::set_permissions(&dest_path, permissions)?;
+            copy_ownership(&source_path, &dest_path)?;
             continue;
         } else if source_path.is_dir() {
             continue;
@@ -300,9 +311,11 @@ fn pkg_install_files(
         // install
         if source_path.is_symlink() || is_symlink(source_path.as_path()) {
             fs::copy(&source_path, &dest_path)?;
+            copy_ownership(&source_path, &dest_path)?;
         } else {
             let temp_dest_path: PathBuf = create_temp_dest_path(&dest_path)?;
             fs::copy(&source_path, &temp_dest_path)?;
+            copy_ownership(&source_path, &temp_dest_path)?;
             fs::rename(&temp_dest_path, &dest_path)?;
         }
 
@@ -318,6 +331,20 @@ fn pkg_install_files(
     Ok(())
 }
 
+// give dest the owner of source, the package was extracted with the owners
+// of its tarball. chown clears the setuid and setgid bits so the mode is set
+// again afterwards.
+fn copy_ownership(source: &Path, dest: &Path) -> Result<(), std::io::Error> {
+    let metadata: fs::Metadata = fs::symlink_metadata(source)?;
+    lchown(dest, Some(metadata.uid()), Some(metadata.gid()))?;
+
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(dest, metadata.permissions())?;
+    }
+
+    Ok(())
+}
+
 // used by pkg_install_files
 fn create_temp_dest_path(dest_path: &Path) -> Result<PathBuf, std::io::Error> {
     let file_name: &OsStr = dest_path.file_name().ok_or_else(|| {
@@ -331,34 +358,31 @@ fn create_temp_dest_path(dest_path: &Path) -> Result<PathBuf, std::io::Error> {
     Ok(temp_dest_path)
 }
 
+// etcsums is the content of the package's etcsums file, configuration files
+// whose checksum is not listed there were modified by the user and are kept.
 fn pkg_remove_files(
     kiss_root: &Path,
     files: &Vec<String>,
+    etcsums: &str,
     debug: bool,
 ) -> Result<(), std::io::Error> {
     let mut broken_symlinks: Vec<PathBuf> = Vec::new();
 
     for file in files {
-        if file.contains("/etc") {
-            let sum_pkg: String = cat(&kiss_root.join(file))?;
-
-            let hash: String = get_file_hash(
-                format!("{}/{}", kiss_root.to_string_lossy(), file)
-                    .replace("//", "/")
-                    .as_str(),
-            )?;
-
-            if hash != sum_pkg {
-                println!("Skipping {} (modified)", file);
-                continue;
-            }
-        }
-
         let relative_file_path: &Path = Path::new(file.as_str())
             .strip_prefix("/")
             .unwrap_or(Path::new(file.as_str()));
         let full_path: PathBuf = kiss_root.join(relative_file_path);
 
+        if file.starts_with("/etc/") && !file.ends_with('/') {
+            if let Ok(sum_sys) = get_file_hash(full_path.to_string_lossy().as_ref()) {
+                if !etcsums.lines().any(|sum| sum.contains(&sum_sys)) {
+                    println!("Skipping {} (modified)", file);
+                    continue;
+                }
+            }
+        }
+
         if let Ok(metadata) = fs::metadata(&full_path) {
             if metadata.is_dir() {
                 // ignore errors when removing directories
@@ -392,18 +416,16 @@ fn pkg_remove_files(
     Ok(())
 }
 
-pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Error> {
+pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), KissError> {
     // Check if the user is running as root
     if !nix::unistd::Uid::effective().is_root() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::PermissionDenied,
-            "This requires root privileges.",
+        return Err(KissError::PermissionDenied(
+            "This requires root privileges.".to_owned(),
         ));
     }
 
     let (pkg, tar_file) = if package_tar.contains(".tar.") {
         (
-            package_tar.to_owned(),
             // remove everything before the last ’/’ and everything after the ’@’ char
             package_tar
                 .rsplit('/')
@@ -413,19 +435,24 @@ pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Er
                 .next()
                 .unwrap()
                 .to_owned(),
+            package_tar.to_owned(),
         )
-    } else if let Some(tarball) = pkg_cache(config, package_tar) {
+    } else if let Some(tarball) = pkg_cache_or_fetch(config, package_tar)? {
         (package_tar.to_owned(), tarball)
     } else {
-        die!(package_tar, "Not yet built");
+        return Err(KissError::InvalidPackage {
+            pkg: package_tar.to_owned(),
+            reason: "Not yet built".to_owned(),
+        });
     };
 
     // cd into extract directory
     let extract_dir: PathBuf = config.tar_dir.join(pkg.as_str());
-    mkcd(extract_dir.to_str().unwrap_or(""));
+    mkcd(extract_dir.to_str().unwrap_or(""))?;
 
-    // extract to current dir
-    pkg_source_tar(&tar_file, &extract_dir, false);
+    // extract to current dir, keeping the owners and permissions of the
+    // package's files
+    pkg_source_tar(&tar_file, &extract_dir, false, true)?;
 
     let manifest_path: PathBuf = extract_dir
         .join(&config.pkg_db)
@@ -433,12 +460,10 @@ pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Er
         .join("manifest");
 
     if !manifest_path.exists() {
-        println!(
-            "{}, {}",
-            extract_dir.to_string_lossy(),
-            manifest_path.to_string_lossy()
-        );
-        die!("Not a valid KISS package");
+        return Err(KissError::InvalidPackage {
+            pkg,
+            reason: "Not a valid KISS package".to_owned(),
+        });
     }
 
     if !config.force {
@@ -447,12 +472,12 @@ pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Er
             pkg.as_str(),
             extract_dir.to_str().unwrap_or(""),
             &manifest_path,
-        );
+        )?;
         pkg_installable(
             config,
             pkg.as_str(),
             &format!("./{}/{}/depends", config.pkg_db, pkg),
-        );
+        )?;
     }
 
     pkg_conflicts(
@@ -465,16 +490,16 @@ pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Er
 
     log!(
         format!("Installing {pkg}"),
-        tar_file
-            .split('/')
-            .last()
-            .expect("Failed to get tar_file name")
+        tar_file.rsplit('/').next().unwrap_or(&tar_file)
     );
 
     //
     let tar_man: String = format!("{}/{}/manifest", config.pkg_db, pkg);
 
     let old_files: Vec<String> = read_a_files_lines(&tar_man)?;
+    // etcsums of the currently installed version, needed to tell apart
+    // modified configuration files when removing leftovers.
+    let etcsums: String = cat(&config.sys_db.join(&pkg).join("etcsums")).unwrap_or_default();
     let new_files: Vec<String> = read_a_files_lines(&manifest_path)?;
 
     // Generate a list of files which exist in the currently installed manifest
@@ -509,8 +534,12 @@ pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Er
         true,
         false,
     );
-    let remove_files_result =
-        pkg_remove_files(Path::new(&config.kiss_root), &manifest_diff, config.debug);
+    let remove_files_result = pkg_remove_files(
+        Path::new(&config.kiss_root),
+        &manifest_diff,
+        &etcsums,
+        config.debug,
+    );
     let install_files_result2 = pkg_install_files(
         config,
         &manifest_reverse,
@@ -521,25 +550,170 @@ pub fn pkg_install(config: &Config, package_tar: &str) -> Result<(), std::io::Er
     );
 
     // handle all errors gracefully
-    match (
+    let result: Result<(), KissError> = match (
         install_files_result,
         remove_files_result,
         install_files_result2,
     ) {
-        (Ok(_), Ok(_), Ok(_)) => log!("Installed successfully", pkg),
-        (Err(err), _, _) => log_and_notify_error("Error installing files", &pkg, err),
-        (_, Err(err), _) => log_and_notify_error("Error removing files", &pkg, err),
-        (_, _, Err(err)) => log_and_notify_error("Error verifying files", &pkg, err),
+        (Ok(_), Ok(_), Ok(_)) => Ok(()),
+        (Err(err), _, _) => Err(dirty_filesystem("Error installing files", &pkg, err)),
+        (_, Err(err), _) => Err(dirty_filesystem("Error removing files", &pkg, err)),
+        (_, _, Err(err)) => Err(dirty_filesystem("Error verifying files", &pkg, err)),
+    };
+
+    // the installed manifest changed even if something failed
+    update_owners_cache(config, &pkg);
+
+    if result.is_ok() {
+        log!("Installed successfully", pkg);
+    }
+
+    result
+}
+
+fn dirty_filesystem(stage: &str, pkg: &str, err: std::io::Error) -> KissError {
+    KissError::DirtyFilesystem {
+        pkg: pkg.to_owned(),
+        stage: stage.to_owned(),
+        source: err,
+    }
+}
+
+// get installed packages which list pkg in their depends file
+fn pkg_rdepends(config: &Config, pkg: &str) -> Result<Vec<String>, KissError> {
+    let installed_packages: Vec<PathBuf> =
+        read_a_dir_and_sort(config.sys_db.to_string_lossy().to_string(), false, &[])?;
+
+    // (package, replacement) of every line in the provides file, a package
+    // depending on a replaced package depends on its replacement
+    let provides: Vec<(String, String)> = read_a_files_lines(&config.provides_db)?
+        .iter()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                [replacement, package] => Some((package.to_owned(), replacement.to_owned())),
+                _ => None,
+            },
+        )
+        .collect();
+    let resolve = |dep: &str| -> String {
+        provides
+            .iter()
+            .find(|(package, _)| package == dep)
+            .map_or(dep, |(_, replacement)| replacement)
+            .to_owned()
+    };
+
+    let mut rdepends: Vec<String> = iter!(installed_packages)
+        .filter_map(|path| {
+            let name: String = path.file_name()?.to_string_lossy().to_string();
+            if name == pkg {
+                return None;
+            }
+
+            let depends: Vec<String> = read_a_files_lines(path.join("depends")).ok()?;
+            depends
+                .iter()
+                .filter(|line| !line.starts_with('#'))
+                .filter_map(|line| line.split_whitespace().next())
+                .any(|dep| dep == pkg || resolve(dep) == pkg)
+                .then_some(name)
+        })
+        .collect();
+
+    sort!(rdepends);
+
+    Ok(rdepends)
+}
+
+pub fn pkg_remove(config: &Config, pkg: &str) -> Result<(), KissError> {
+    // Check if the user is running as root
+    if !nix::unistd::Uid::effective().is_root() {
+        return Err(KissError::PermissionDenied(
+            "This requires root privileges.".to_owned(),
+        ));
     }
 
+    let pkg_db_dir: PathBuf = config.sys_db.join(pkg);
+    let manifest_path: PathBuf = pkg_db_dir.join("manifest");
+
+    if !manifest_path.exists() {
+        return Err(KissError::NotInstalled(pkg.to_owned()));
+    }
+
+    // Make sure that nothing depends on this package.
+    if !config.force {
+        if config.debug || config.verbose {
+            log!(pkg, "Checking for reverse dependencies");
+        }
+
+        let rdepends: Vec<String> = pkg_rdepends(config, pkg)?;
+        if !rdepends.is_empty() {
+            return Err(KissError::Dependents {
+                pkg: pkg.to_owned(),
+                rdepends,
+            });
+        }
+    }
+
+    // Keep a copy of etcsums, the database entry is removed last.
+    let etcsums: String = cat(&pkg_db_dir.join("etcsums")).unwrap_or_default();
+    let manifest: Vec<String> = read_a_files_lines(&manifest_path)?;
+
+    // Split out the package's own database entry. It is only removed once
+    // every other file is gone so an interrupted removal can be retried.
+    let db_prefix: String = format!("/{}/{}/", config.pkg_db, pkg);
+    let files: Vec<String> = manifest
+        .into_iter()
+        .filter(|file| !file.starts_with(&db_prefix))
+        .collect();
+
+    log!(pkg, "Removing package");
+
+    pkg_remove_files(Path::new(&config.kiss_root), &files, &etcsums, config.debug)?;
+    fs::remove_dir_all(&pkg_db_dir)?;
+    update_owners_cache(config, pkg);
+
+    log!(pkg, "Removed successfully");
+
     Ok(())
 }
 
-fn log_and_notify_error(log: &str, pkg: &String, err: impl std::error::Error) {
-    log!(log, err);
-    die!(
-        "Error installing",
-        format!("{pkg}:"),
-        "Filesystem is now dirty, manual repair is needed."
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installable_packages() {
+        let dir: PathBuf = std::env::temp_dir().join(format!(
+            "kiss-test-{}-installable-packages",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("installed/musl")).unwrap();
+        fs::create_dir_all(dir.join("installed/libressl")).unwrap();
+
+        let mut config: Config = Config::new();
+        config.sys_db = dir.join("installed");
+        config.provides_db = dir.join("provides");
+        fs::write(&config.provides_db, "libressl openssl\n").unwrap();
+
+        let depends: PathBuf = dir.join("depends");
+        let depends_path: String = depends.to_string_lossy().to_string();
+
+        // installed, replaced through provides and make only dependencies
+        fs::write(&depends, "# comment\nmusl\nopenssl\ncmake make\n").unwrap();
+        assert!(pkg_installable(&config, "foo", &depends_path).is_ok());
+
+        fs::write(&depends, "musl\nzlib\nxz\ncmake make\n").unwrap();
+        match pkg_installable(&config, "foo", &depends_path) {
+            Err(KissError::NotInstallable { pkg, missing }) => {
+                assert_eq!(pkg, "foo");
+                assert_eq!(missing, ["zlib", "xz"]);
+            }
+            result => panic!("expected a missing dependency, got {:?}", result),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,7 +1,12 @@
+pub mod alternatives_lib;
+pub mod binrepo_lib;
 pub mod build_lib;
 pub mod checksum_lib;
+pub mod download_lib;
 pub mod install;
+pub mod lint_lib;
 pub mod manifest_lib;
+pub mod owns_lib;
 pub mod provides_lib;
 pub mod search_lib;
 pub mod shared_lib;
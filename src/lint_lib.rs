@@ -0,0 +1,457 @@
+use crate::search_lib::pkg_find_path;
+use crate::shared_lib::{
+    globals::Config, read_a_files_lines, sources::parse_source, KissError, SourceKind,
+};
+
+// logging
+use crate::log;
+
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "WARNING"),
+            Severity::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+// a problem found in a package, message starts with the file it is in
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+// lint every package and print what was found, fails if there were errors
+//
+// packages containing a '/' are package directories, everything else is
+// looked up in KISS_PATH.
+pub fn pkg_lint_all<T: AsRef<str>>(config: &Config, packages: &[T]) -> Result<(), KissError> {
+    let mut errors: usize = 0;
+    let mut warnings: usize = 0;
+
+    for package in packages {
+        let package: &str = package.as_ref();
+
+        // paths like ./ or . are canonicalized so the package is named after
+        // the directory
+        let (pkg, repo_dir): (String, PathBuf) =
+            if package.contains('/') || package == "." || package == ".." {
+                let repo_dir: PathBuf = fs::canonicalize(package)
+                    .ok()
+                    .filter(|repo_dir| repo_dir.is_dir())
+                    .ok_or_else(|| KissError::NotFound(package.to_owned()))?;
+                let pkg: String = repo_dir
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .ok_or_else(|| KissError::NotFound(package.to_owned()))?;
+                (pkg, repo_dir)
+            } else {
+                let repo_dir: PathBuf = pkg_find_path(config, package, None)
+                    .ok_or_else(|| KissError::NotFound(package.to_owned()))?;
+                (package.to_owned(), repo_dir)
+            };
+        let pkg: &str = &pkg;
+
+        let findings: Vec<Finding> = pkg_lint(config, pkg, &repo_dir)?;
+
+        for finding in &findings {
+            println!("{}: {}: {}", finding.severity, pkg, finding.message);
+            match finding.severity {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+            }
+        }
+
+        if findings.is_empty() {
+            log!(pkg, "No problems found");
+        }
+    }
+
+    if errors > 0 {
+        return Err(KissError::LintFailed { errors, warnings });
+    }
+
+    Ok(())
+}
+
+// check a package directory, every problem is returned instead of stopping
+// at the first one
+pub fn pkg_lint(config: &Config, pkg: &str, repo_dir: &Path) -> Result<Vec<Finding>, KissError> {
+    let mut findings: Vec<Finding> = Vec::new();
+
+    lint_version(repo_dir, &mut findings)?;
+    lint_build(repo_dir, &mut findings)?;
+    lint_sources(repo_dir, &mut findings)?;
+    lint_depends(config, pkg, repo_dir, &mut findings)?;
+    lint_shadows(config, pkg, repo_dir, &mut findings);
+
+    Ok(findings)
+}
+
+fn error(findings: &mut Vec<Finding>, message: String) {
+    findings.push(Finding {
+        severity: Severity::Error,
+        message,
+    });
+}
+
+fn warning(findings: &mut Vec<Finding>, message: String) {
+    findings.push(Finding {
+        severity: Severity::Warning,
+        message,
+    });
+}
+
+// version must be a single '<version> <release>' line
+fn lint_version(repo_dir: &Path, findings: &mut Vec<Finding>) -> Result<(), KissError> {
+    let version_file: PathBuf = repo_dir.join("version");
+
+    if !version_file.is_file() {
+        error(findings, String::from("version: File not found"));
+        return Ok(());
+    }
+
+    let lines: Vec<String> = read_a_files_lines(&version_file)?;
+    let fields: Vec<&str> = match lines.as_slice() {
+        [line] => line.split_whitespace().collect(),
+        _ => {
+            error(
+                findings,
+                format!("version: Expected 1 line, found {}", lines.len()),
+            );
+            return Ok(());
+        }
+    };
+
+    match fields.as_slice() {
+        [_, release] if !release.chars().all(|c| c.is_ascii_digit()) => warning(
+            findings,
+            format!("version:1: Release '{}' is not a number", release),
+        ),
+        [_, _] => {}
+        _ => error(
+            findings,
+            format!(
+                "version:1: Expected '<version> <release>', found {} field(s)",
+                fields.len()
+            ),
+        ),
+    }
+
+    Ok(())
+}
+
+// build must exist and be executable
+fn lint_build(repo_dir: &Path, findings: &mut Vec<Finding>) -> Result<(), KissError> {
+    let build_file: PathBuf = repo_dir.join("build");
+
+    if !build_file.is_file() {
+        error(findings, String::from("build: File not found"));
+    } else if fs::metadata(&build_file)?.permissions().mode() & 0o111 == 0 {
+        error(findings, String::from("build: Not executable"));
+    }
+
+    Ok(())
+}
+
+// every line of sources must parse and every source but git ones needs a
+// line in checksums
+fn lint_sources(repo_dir: &Path, findings: &mut Vec<Finding>) -> Result<(), KissError> {
+    let sources_file: PathBuf = repo_dir.join("sources");
+    let checksums_file: PathBuf = repo_dir.join("checksums");
+
+    let checksums: usize = read_a_files_lines(&checksums_file)?
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .count();
+
+    if !sources_file.exists() {
+        if checksums_file.exists() {
+            warning(findings, String::from("checksums: Found without sources"));
+        }
+        return Ok(());
+    }
+
+    let mut sources: usize = 0;
+
+    for (index, line) in read_a_files_lines(&sources_file)?.iter().enumerate() {
+        match parse_source(line) {
+            Ok(Some(source)) if source.kind != SourceKind::Git => sources += 1,
+            Ok(_) => {}
+            Err(reason) => error(findings, format!("sources:{}: {}", index + 1, reason)),
+        }
+    }
+
+    if sources != checksums {
+        error(
+            findings,
+            format!(
+                "checksums: {} source(s) need a checksum but {} checksum(s) found",
+                sources, checksums
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+// every dependency must be in KISS_PATH
+fn lint_depends(
+    config: &Config,
+    pkg: &str,
+    repo_dir: &Path,
+    findings: &mut Vec<Finding>,
+) -> Result<(), KissError> {
+    for (index, line) in read_a_files_lines(repo_dir.join("depends"))?
+        .iter()
+        .enumerate()
+    {
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let dep: &str = fields[0];
+
+        match fields.as_slice() {
+            [_] | [_, "make"] => {}
+            [_, kind] => warning(
+                findings,
+                format!("depends:{}: Unknown dependency type '{}'", index + 1, kind),
+            ),
+            _ => error(
+                findings,
+                format!("depends:{}: Expected '<package> [make]'", index + 1),
+            ),
+        }
+
+        if dep == pkg {
+            error(
+                findings,
+                format!("depends:{}: Package depends on itself", index + 1),
+            );
+        } else if pkg_find_path(config, dep, None).is_none() {
+            error(
+                findings,
+                format!("depends:{}: '{}' not found in KISS_PATH", index + 1, dep),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// a package in more than one repository, only the first one is used. a
+// package directory outside of KISS_PATH is shadowed by any package of the
+// same name in it.
+fn lint_shadows(config: &Config, pkg: &str, repo_dir: &Path, findings: &mut Vec<Finding>) {
+    let found: Vec<PathBuf> = config
+        .kiss_path
+        .iter()
+        .map(|repo| Path::new(repo).join(pkg))
+        .filter(|path| path.is_dir())
+        .map(|path| fs::canonicalize(&path).unwrap_or(path))
+        .collect();
+
+    let repo_dir: PathBuf = fs::canonicalize(repo_dir).unwrap_or(repo_dir.to_path_buf());
+    let shadowed: Vec<&PathBuf> = match found.contains(&repo_dir) {
+        true => found.iter().skip(1).collect(),
+        false => found.iter().skip(1).chain([&repo_dir]).collect(),
+    };
+
+    if let Some(used) = found.first() {
+        for path in shadowed {
+            warning(
+                findings,
+                format!("{} shadows {}", used.display(), path.display()),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lint package foo inside a repository which also has a package bar,
+    // files override the defaults of a valid package
+    fn lint(name: &str, files: &[(&str, &str)]) -> Vec<(Severity, String)> {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+
+        for pkg in ["foo", "bar"] {
+            let repo_dir: PathBuf = dir.join("repo").join(pkg);
+            fs::create_dir_all(&repo_dir).unwrap();
+            fs::write(repo_dir.join("version"), "1.0 1\n").unwrap();
+            fs::write(repo_dir.join("build"), "#!/bin/sh -e\n").unwrap();
+            fs::set_permissions(repo_dir.join("build"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let repo_dir: PathBuf = dir.join("repo").join("foo");
+        for (file, content) in files {
+            fs::write(repo_dir.join(file), content).unwrap();
+        }
+
+        let mut config: Config = Config::new();
+        config.kiss_path = vec![dir.join("repo").to_string_lossy().to_string()];
+
+        let findings: Vec<(Severity, String)> = pkg_lint(&config, "foo", &repo_dir)
+            .unwrap()
+            .into_iter()
+            .map(|finding| (finding.severity, finding.message))
+            .collect();
+
+        fs::remove_dir_all(&dir).unwrap();
+        findings
+    }
+
+    #[test]
+    fn valid_package() {
+        assert_eq!(lint("lint-valid", &[]), vec![]);
+    }
+
+    #[test]
+    fn version_fields() {
+        assert_eq!(
+            lint("lint-version-fields", &[("version", "1.0\n")]),
+            vec![(
+                Severity::Error,
+                String::from("version:1: Expected '<version> <release>', found 1 field(s)")
+            )]
+        );
+        assert_eq!(
+            lint("lint-version-release", &[("version", "1.0 r1\n")]),
+            vec![(
+                Severity::Warning,
+                String::from("version:1: Release 'r1' is not a number")
+            )]
+        );
+    }
+
+    #[test]
+    fn build_not_executable() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-lint-build", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("build"), "#!/bin/sh -e\n").unwrap();
+        fs::set_permissions(dir.join("build"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut findings: Vec<Finding> = Vec::new();
+        lint_build(&dir, &mut findings).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].message, "build: Not executable");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn git_sources_need_no_checksum() {
+        let sources: &str = "https://host/foo.tar.gz\ngit+https://host/bar@v1.0\n";
+
+        assert_eq!(
+            lint(
+                "lint-checksums",
+                &[("sources", sources), ("checksums", "aa\n")]
+            ),
+            vec![]
+        );
+        assert_eq!(
+            lint(
+                "lint-checksums-count",
+                &[("sources", sources), ("checksums", "aa\nbb\n")]
+            ),
+            vec![(
+                Severity::Error,
+                String::from("checksums: 1 source(s) need a checksum but 2 checksum(s) found")
+            )]
+        );
+    }
+
+    #[test]
+    fn self_dependency() {
+        assert_eq!(
+            lint("lint-self-dependency", &[("depends", "bar\nfoo\n")]),
+            vec![(
+                Severity::Error,
+                String::from("depends:2: Package depends on itself")
+            )]
+        );
+    }
+
+    #[test]
+    fn dependency_types() {
+        assert_eq!(
+            lint(
+                "lint-dependency-types",
+                &[("depends", "bar make\nbar test\nbaz\n")]
+            ),
+            vec![
+                (
+                    Severity::Warning,
+                    String::from("depends:2: Unknown dependency type 'test'")
+                ),
+                (
+                    Severity::Error,
+                    String::from("depends:3: 'baz' not found in KISS_PATH")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowed_package_directory() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-lint-shadows", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for repo in ["first", "second", "outside"] {
+            fs::create_dir_all(dir.join(repo).join("foo")).unwrap();
+        }
+
+        let mut config: Config = Config::new();
+        config.kiss_path = ["first", "second"]
+            .iter()
+            .map(|repo| dir.join(repo).to_string_lossy().to_string())
+            .collect();
+
+        let canonical = |repo: &str| fs::canonicalize(dir.join(repo).join("foo")).unwrap();
+        let shadows = |repo: &str| {
+            let mut findings: Vec<Finding> = Vec::new();
+            lint_shadows(&config, "foo", &dir.join(repo).join("foo"), &mut findings);
+            findings
+                .into_iter()
+                .map(|finding| finding.message)
+                .collect::<Vec<String>>()
+        };
+
+        let first_shadows = |repo: &str| {
+            format!(
+                "{} shadows {}",
+                canonical("first").display(),
+                canonical(repo).display()
+            )
+        };
+
+        assert_eq!(shadows("first"), vec![first_shadows("second")]);
+        assert_eq!(
+            shadows("outside"),
+            vec![first_shadows("second"), first_shadows("outside")]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -7,26 +7,28 @@ use std::sync::{RwLockReadGuard, RwLockWriteGuard};
 use std::path::Path;
 use std::process::exit;
 
+use kiss::alternatives_lib::{pkg_alternatives, pkg_swap};
 use kiss::build_lib::pkg_build_all;
 use kiss::checksum_lib::pkg_checksum;
-use kiss::install::pkg_install;
+use kiss::install::{pkg_install, pkg_remove};
+use kiss::lint_lib::pkg_lint_all;
+use kiss::owns_lib::pkg_owns;
 use kiss::provides_lib::{add_remove_from_provides, list_provides};
 use kiss::search_lib::pkg_find;
 use kiss::shared_lib::{
     am_owner, cat, get_current_working_dir, get_directory_name,
     globals::{get_config, set_config, Config, Dependencies, DEPENDENCIES},
-    log, read_a_dir_and_sort,
+    log, read_a_dir_and_sort, KissError,
 };
-use kiss::source_lib::{get_repositories, pkg_source, pkg_update_repo};
+use kiss::source_lib::{get_repositories, pkg_source_all, pkg_update_repo};
 use kiss::upgrade_lib::pkg_upgrade;
 
-use kiss::die;
 use kiss::shared_lib::signal::pkg_clean;
 
 // threading
 use kiss::{iter, sort};
 #[cfg(feature = "threading")]
-use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::iter::ParallelIterator;
 
 use nix::unistd::Uid;
 
@@ -38,21 +40,42 @@ fn main() {
         .description(env!("CARGO_PKG_DESCRIPTION"))
         .version(env!("CARGO_PKG_VERSION"))
         .usage(format!("{} [flags] <packages>", env!("CARGO_PKG_NAME")))
+        .command(
+            Command::new("alternatives")
+                .description("List and swap alternatives")
+                .alias("a")
+                .usage(format!(
+                    "{} alternatives [<pkg> <path>]",
+                    env!("CARGO_PKG_NAME")
+                ))
+                .flag(debug_flag())
+                .flag(pid_flag())
+                .flag(kiss_root_flag())
+                .flag(kiss_tmp_dir_flag())
+                .action(alternatives_action),
+        )
         .command(
             Command::new("build")
                 .description("Build packages")
                 .alias("b")
+                .flag(choice_flag())
                 .flag(debug_flag())
                 .flag(force_flag())
+                .flag(keep_logs_flag())
                 .flag(prompt_flag())
                 .flag(verbose_flag())
                 .flag(strip_flag())
                 .flag(pid_flag())
+                .flag(build_user_flag())
+                .flag(as_user_flag())
+                .flag(download_jobs_flag())
                 .flag(kiss_compress_flag())
+                .flag(compress_level_flag())
                 .flag(kiss_cache_dir_flag())
                 .flag(kiss_path_flag())
                 .flag(kiss_root_flag())
                 .flag(kiss_tmp_dir_flag())
+                .flag(offline_flag())
                 .flag(jobs_flag())
                 .action(build_action),
         )
@@ -67,6 +90,8 @@ fn main() {
                 .flag(kiss_path_flag())
                 .flag(kiss_root_flag())
                 .flag(kiss_tmp_dir_flag())
+                .flag(download_jobs_flag())
+                .flag(offline_flag())
                 .flag(jobs_flag())
                 .action(checksum_action),
         )
@@ -81,6 +106,8 @@ fn main() {
                 .flag(kiss_path_flag())
                 .flag(kiss_root_flag())
                 .flag(kiss_tmp_dir_flag())
+                .flag(download_jobs_flag())
+                .flag(offline_flag())
                 .flag(jobs_flag())
                 .action(download_action),
         )
@@ -88,6 +115,7 @@ fn main() {
             Command::new("install")
                 .description("Install packages")
                 .alias("i")
+                .flag(choice_flag())
                 .flag(debug_flag())
                 .flag(force_flag())
                 .flag(prompt_flag())
@@ -95,6 +123,7 @@ fn main() {
                 .flag(strip_flag())
                 .flag(pid_flag())
                 .flag(kiss_compress_flag())
+                .flag(compress_level_flag())
                 .flag(kiss_cache_dir_flag())
                 .flag(kiss_path_flag())
                 .flag(kiss_root_flag())
@@ -102,6 +131,13 @@ fn main() {
                 .flag(jobs_flag())
                 .action(install_action),
         )
+        .command(
+            Command::new("lint")
+                .description("Check packages for common mistakes")
+                .usage(format!("{} lint [<pkg>|<path>...]", env!("CARGO_PKG_NAME")))
+                .flag(kiss_path_flag())
+                .action(lint_action),
+        )
         .command(
             Command::new("list")
                 .description("List installed packages")
@@ -115,6 +151,14 @@ fn main() {
                 .flag(jobs_flag())
                 .action(list_action),
         )
+        .command(
+            Command::new("owns")
+                .description("Print the package which owns a file")
+                .alias("o")
+                .usage(format!("{} owns <path>", env!("CARGO_PKG_NAME")))
+                .flag(kiss_root_flag())
+                .action(owns_action),
+        )
         .command(
             Command::new("provides")
                 .description("add/remove replacements from provides file")
@@ -125,6 +169,18 @@ fn main() {
                 ))
                 .action(provides_action),
         )
+        .command(
+            Command::new("remove")
+                .description("Remove packages")
+                .alias("r")
+                .flag(debug_flag())
+                .flag(force_flag())
+                .flag(verbose_flag())
+                .flag(pid_flag())
+                .flag(kiss_root_flag())
+                .flag(kiss_tmp_dir_flag())
+                .action(remove_action),
+        )
         .command(
             Command::new("search")
                 .description("Search packages")
@@ -153,17 +209,23 @@ fn main() {
             Command::new("upgrade")
                 .description("Upgrade the system")
                 .alias("U")
+                .flag(choice_flag())
                 .flag(debug_flag())
                 .flag(force_flag())
+                .flag(keep_logs_flag())
                 .flag(prompt_flag())
                 .flag(verbose_flag())
                 .flag(strip_flag())
                 .flag(pid_flag())
+                .flag(build_user_flag())
+                .flag(download_jobs_flag())
                 .flag(kiss_compress_flag())
+                .flag(compress_level_flag())
                 .flag(kiss_cache_dir_flag())
                 .flag(kiss_path_flag())
                 .flag(kiss_root_flag())
                 .flag(kiss_tmp_dir_flag())
+                .flag(offline_flag())
                 .flag(jobs_flag())
                 .action(upgrade_action),
         )
@@ -172,6 +234,7 @@ fn main() {
                 .description("Update the repositories")
                 .alias("u")
                 .flag(kiss_path_flag())
+                .flag(offline_flag())
                 .flag(jobs_flag())
                 .action(update_action),
         );
@@ -180,20 +243,77 @@ fn main() {
     exit(pkg_clean());
 }
 
+// every error ends up here, clean up and exit with a code scripts can check
+fn exit_on_error<T>(result: Result<T, KissError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            pkg_clean();
+            exit(exit_code(&err));
+        }
+    }
+}
+
+fn exit_code(err: &KissError) -> i32 {
+    match err {
+        KissError::NotFound(_) | KissError::NotInstalled(_) => 2,
+        KissError::ChecksumMismatch { .. } | KissError::ChecksumCount { .. } => 3,
+        KissError::BuildFailed { .. } => 4,
+        KissError::Conflict { .. }
+        | KissError::Dependents { .. }
+        | KissError::NotInstallable { .. }
+        | KissError::CircularDependency(_) => 5,
+        _ => 1,
+    }
+}
+
+fn alternatives_action(c: &Context) {
+    match c.args.len() {
+        0 => {
+            exit_on_error(set_config(c, false));
+            let config: RwLockReadGuard<'_, Config> = get_config();
+
+            exit_on_error(pkg_alternatives(&config));
+        }
+        2 => {
+            // Check if the user is running as root
+            if !Uid::effective().is_root() {
+                eprintln!("This application must be run as root.");
+                exit(1);
+            }
+
+            exit_on_error(set_config(c, true));
+            let config: RwLockReadGuard<'_, Config> = get_config();
+
+            exit_on_error(pkg_swap(&config, &c.args[0], &c.args[1]));
+        }
+        _ => {
+            eprintln!(
+                "ERROR: {} alternatives accepts either 0 or 2 args",
+                env!("CARGO_PKG_NAME")
+            );
+            exit(1);
+        }
+    }
+}
+
 fn build_action(c: &Context) {
-    // Check if the user is running as root
-    if !Uid::effective().is_root() {
+    exit_on_error(set_config(c, true));
+    let config: RwLockReadGuard<'_, Config> = get_config();
+
+    // Check if the user is running as root, builds only run as the invoking
+    // user when explicitly asked for.
+    if !config.as_user && !Uid::effective().is_root() {
         eprintln!("This application must be run as root.");
+        eprintln!("Use --as-user or KISS_BUILD_AS_USER=1 to build as the current user.");
         exit(1);
     }
-
-    set_config(c, true);
-    let config: RwLockReadGuard<'_, Config> = get_config();
     let mut dependencies: RwLockWriteGuard<'_, Dependencies> = DEPENDENCIES.write().unwrap();
 
     let packages: Vec<&str> = get_args(c);
 
-    pkg_build_all(&config, &mut dependencies, packages);
+    exit_on_error(pkg_build_all(&config, &mut dependencies, packages));
 }
 
 fn checksum_action(c: &Context) {
@@ -203,17 +323,17 @@ fn checksum_action(c: &Context) {
         exit(1);
     }
 
-    set_config(c, false);
+    exit_on_error(set_config(c, false));
     let config: RwLockReadGuard<'_, Config> = get_config();
 
     let packages: Vec<&str> = get_args(c);
 
     if !packages.is_empty() {
         for package in packages {
-            pkg_checksum(&config, package);
+            exit_on_error(pkg_checksum(&config, package));
         }
     } else {
-        pkg_checksum(&config, "");
+        exit_on_error(pkg_checksum(&config, ""));
     }
 }
 
@@ -224,17 +344,15 @@ fn download_action(c: &Context) {
         exit(1);
     }
 
-    set_config(c, true);
+    exit_on_error(set_config(c, true));
     let config: RwLockReadGuard<'_, Config> = get_config();
     // get packages
     let packages: Vec<&str> = get_args(c);
 
     if !packages.is_empty() {
-        iter!(packages).enumerate().for_each(|(_, package)| {
-            pkg_source(&config, package, false, true);
-        });
+        exit_on_error(pkg_source_all(&config, &packages, false, true));
     } else {
-        pkg_source(&config, "", false, true);
+        exit_on_error(pkg_source_all(&config, &[""], false, true));
     }
 }
 
@@ -245,24 +363,38 @@ fn install_action(c: &Context) {
         exit(1);
     }
 
-    set_config(c, true);
+    exit_on_error(set_config(c, true));
     let config: RwLockReadGuard<'_, Config> = get_config();
 
     let packages: Vec<&str> = get_args(c);
 
     if !packages.is_empty() {
         for package in packages {
-            pkg_install(&config, package).expect("Failed to install package");
+            exit_on_error(pkg_install(&config, package));
         }
     } else {
         let current_dir: String = get_current_working_dir();
         let package: &str = get_directory_name(&current_dir);
-        pkg_install(&config, package).expect("Failed to install package");
+        exit_on_error(pkg_install(&config, package));
+    }
+}
+
+fn lint_action(c: &Context) {
+    exit_on_error(set_config(c, false));
+    let config: RwLockReadGuard<'_, Config> = get_config();
+
+    let packages: Vec<&str> = get_args(c);
+
+    if !packages.is_empty() {
+        exit_on_error(pkg_lint_all(&config, &packages));
+    } else {
+        let current_dir: String = get_current_working_dir();
+        exit_on_error(pkg_lint_all(&config, &[current_dir]));
     }
 }
 
 fn list_action(c: &Context) {
-    set_config(c, false);
+    exit_on_error(set_config(c, false));
 
     let version_param: bool = c.bool_flag("version");
     let config: RwLockReadGuard<'_, Config> = get_config();
@@ -272,28 +404,27 @@ fn list_action(c: &Context) {
 
     if search.is_empty() {
         // get installed packages
-        let mut installed_packages: Vec<_> = iter!(read_a_dir_and_sort(
-            &*config.sys_db.to_string_lossy(),
-            false,
-            &[]
-        ))
-        .enumerate()
-        .map(|(_, package)| {
-            let file_name = match package.file_name() {
-                Some(file_name) => file_name.to_str().unwrap_or(""),
-                None => "",
-            };
-            if version_param {
-                let version: String = cat(&package.join("version"))
-                    .unwrap()
-                    .replace(' ', "-")
-                    .replace('\n', "");
-                format!("{} {}", file_name, version)
-            } else {
-                file_name.to_owned()
-            }
-        })
-        .collect();
+        let installed_packages: Vec<_> = exit_on_error(
+            read_a_dir_and_sort(&*config.sys_db.to_string_lossy(), false, &[])
+                .map_err(KissError::from),
+        );
+        let mut installed_packages: Vec<_> = iter!(installed_packages)
+            .map(|package| {
+                let file_name = match package.file_name() {
+                    Some(file_name) => file_name.to_str().unwrap_or(""),
+                    None => "",
+                };
+                if version_param {
+                    let version: String = cat(&package.join("version"))
+                        .unwrap_or_default()
+                        .replace(' ', "-")
+                        .replace('\n', "");
+                    format!("{} {}", file_name, version)
+                } else {
+                    file_name.to_owned()
+                }
+            })
+            .collect();
 
         // sort and print
         sort!(installed_packages);
@@ -301,7 +432,7 @@ fn list_action(c: &Context) {
             println!("{}", package)
         }
     } else {
-        let result: Vec<_> = iter!(search)
+        let result: Vec<Result<String, KissError>> = iter!(search)
             .map(|package| {
                 let path: &Path = &Path::new(&config.sys_db).join(package);
                 if path.exists() {
@@ -311,39 +442,53 @@ fn list_action(c: &Context) {
                     };
                     if version_param {
                         let version: String = cat(&path.join("version"))
-                            .unwrap()
+                            .unwrap_or_default()
                             .replace(' ', "-")
                             .replace('\n', "");
-                        format!("{} {}", file_name, version)
+                        Ok(format!("{} {}", file_name, version))
                     } else {
-                        file_name.to_owned()
+                        Ok(file_name.to_owned())
                     }
                 } else {
-                    format!("{} not found", package)
+                    Err(KissError::NotInstalled(package.to_string()))
                 }
             })
             .collect();
 
         // sort and print
         for res in result {
-            if res.contains("not found") {
-                die!(res);
-            } else {
-                println!("{}", res);
-            }
+            println!("{}", exit_on_error(res));
         }
     }
 }
 
+fn owns_action(c: &Context) {
+    exit_on_error(set_config(c, false));
+    let config: RwLockReadGuard<'_, Config> = get_config();
+
+    if c.args.len() != 1 {
+        eprintln!(
+            "ERROR: {} owns accepts exactly 1 arg",
+            env!("CARGO_PKG_NAME")
+        );
+        exit(1);
+    }
+
+    match exit_on_error(pkg_owns(&config, &c.args[0])) {
+        Some(owner) => println!("{}", owner),
+        None => exit_on_error(Err(KissError::Other(format!(
+            "{}: Package manager doesn't own this file",
+            c.args[0]
+        )))),
+    }
+}
+
 fn provides_action(c: &Context) {
-    set_config(c, false);
+    exit_on_error(set_config(c, false));
     let config: RwLockReadGuard<'_, Config> = get_config();
 
     if c.args.is_empty() {
-        if let Err(err) = list_provides(&config.provides_db) {
-            eprintln!("ERROR: {}", err);
-            exit(1);
-        };
+        exit_on_error(list_provides(&config.provides_db).map_err(KissError::from));
     } else if c.args.len() <= 2 {
         let replacement: Option<&str> = if c.args.len() == 1 {
             None
@@ -356,10 +501,10 @@ fn provides_action(c: &Context) {
             c.args[1].as_str()
         };
 
-        if let Err(err) = add_remove_from_provides(&config.provides_db, replacement, replaces) {
-            eprintln!("ERROR: {}", err);
-            exit(1);
-        }
+        exit_on_error(
+            add_remove_from_provides(&config.provides_db, replacement, replaces)
+                .map_err(KissError::from),
+        );
     } else {
         eprintln!(
             "ERROR: {} does not accept more than 2 args",
@@ -369,8 +514,31 @@ fn provides_action(c: &Context) {
     }
 }
 
+fn remove_action(c: &Context) {
+    // Check if the user is running as root
+    if !Uid::effective().is_root() {
+        eprintln!("This application must be run as root.");
+        exit(1);
+    }
+
+    exit_on_error(set_config(c, true));
+    let config: RwLockReadGuard<'_, Config> = get_config();
+
+    let packages: Vec<&str> = get_args(c);
+
+    if !packages.is_empty() {
+        for package in packages {
+            exit_on_error(pkg_remove(&config, package));
+        }
+    } else {
+        let current_dir: String = get_current_working_dir();
+        let package: &str = get_directory_name(&current_dir);
+        exit_on_error(pkg_remove(&config, package));
+    }
+}
+
 fn search_action(c: &Context) {
-    set_config(c, false);
+    exit_on_error(set_config(c, false));
     let config: RwLockReadGuard<'_, Config> = get_config();
 
     let all: bool = c.bool_flag("all");
@@ -392,9 +560,14 @@ fn search_action(c: &Context) {
 }
 
 fn update_action(c: &Context) {
-    set_config(c, false);
+    exit_on_error(set_config(c, false));
     let config: RwLockReadGuard<'_, Config> = get_config();
 
+    if config.offline {
+        eprintln!("WARNING: Offline mode, not updating repositories");
+        return;
+    }
+
     let repositories: Vec<String> = get_repositories(&config.kiss_path);
 
     println!("Updating repositories");
@@ -405,7 +578,7 @@ fn update_action(c: &Context) {
             eprintln!("This repository requires you to run this command as {user}",);
             exit(1);
         }
-        if let Err(err) = pkg_update_repo(repo_path) {
+        if let Err(err) = pkg_update_repo(&config, repo_path) {
             eprintln!("Error updating repository {}: {}", repo_path, err);
         }
     })
@@ -418,9 +591,9 @@ fn upgrade_action(c: &Context) {
         exit(1);
     }
 
-    set_config(c, true);
+    exit_on_error(set_config(c, true));
     let config: RwLockReadGuard<'_, Config> = get_config();
     let mut dependencies: RwLockWriteGuard<'_, Dependencies> = DEPENDENCIES.write().unwrap();
 
-    pkg_upgrade(&config, &mut dependencies);
+    exit_on_error(pkg_upgrade(&config, &mut dependencies));
 }
@@ -1,10 +1,9 @@
 use crate::shared_lib::{
-    globals::Config, is_symlink, read_a_dir_and_sort, read_a_files_lines, tmp_file,
+    globals::Config, is_symlink, read_a_dir_and_sort, read_a_files_lines, tmp_file, KissError,
 };
 
 // logging
-use crate::shared_lib::signal::pkg_clean;
-use crate::{die, log};
+use crate::log;
 
 // libs
 use std::{
@@ -17,11 +16,10 @@ use crate::{iter, sort_reverse};
 #[cfg(feature = "threading")]
 use rayon::iter::ParallelIterator;
 
-pub fn pkg_manifest(config: &Config, pkg: &str, dir: &Path) {
+pub fn pkg_manifest(config: &Config, pkg: &str, dir: &Path) -> Result<(), KissError> {
     log!(pkg, "Generating manifest");
 
-    let (mut tmp_file, tmp_file_path) =
-        tmp_file(config, pkg, "manifest").expect("Failed to create tmp_file");
+    let (mut tmp_file, tmp_file_path) = tmp_file(config, pkg, "manifest")?;
 
     // Create a list of all files and directories. Append '/' to the end of
     // directories so they can be easily filtered out later. Also filter out
@@ -37,13 +35,12 @@ pub fn pkg_manifest(config: &Config, pkg: &str, dir: &Path) {
 
     // remove manifest file if it already exists
     if pkg_manifest_pathbuf.exists() {
-        std::fs::remove_file(&pkg_manifest_pathbuf)
-            .expect("Failed to remove already existing manifest file");
+        std::fs::remove_file(&pkg_manifest_pathbuf)?;
     }
 
     // read contents of directory
     let mut files: Vec<PathBuf> =
-        read_a_dir_and_sort(prefix.as_str(), true, &[".la", "charset.alias"]);
+        read_a_dir_and_sort(prefix.as_str(), true, &[".la", "charset.alias"])?;
     files.push(pkg_manifest_pathbuf.to_owned());
 
     // remove prefix
@@ -73,27 +70,28 @@ pub fn pkg_manifest(config: &Config, pkg: &str, dir: &Path) {
     sort_reverse!(manifest);
 
     for file in manifest {
-        tmp_file
-            .write_all(file.into_os_string().into_string().unwrap().as_bytes())
-            .expect("Failed to write manifest to tmp file");
-        tmp_file
-            .write_all(b"\n")
-            .expect("Failed to write manifest to tmp file");
+        tmp_file.write_all(file.to_string_lossy().as_bytes())?;
+        tmp_file.write_all(b"\n")?;
     }
 
     // copy manifest file to actual dest
-    std::fs::copy(tmp_file_path, pkg_manifest_pathbuf)
-        .expect("Failed to copy tmp_file to actual manifest path");
+    std::fs::copy(tmp_file_path, pkg_manifest_pathbuf)?;
+
+    Ok(())
 }
 
-pub fn pkg_manifest_validate(config: &Config, pkg: &str, path: &str, manifest_path: &PathBuf) {
+pub fn pkg_manifest_validate(
+    config: &Config,
+    pkg: &str,
+    path: &str,
+    manifest_path: &PathBuf,
+) -> Result<(), KissError> {
     // debug comes from caller
     if config.debug || config.verbose {
         log!(pkg, "Checking if manifest is valid");
     }
 
-    let relative_manifest_elements: Vec<String> = read_a_files_lines(manifest_path)
-        .expect("Failed to read manifest file")
+    let relative_manifest_elements: Vec<String> = read_a_files_lines(manifest_path)?
         .iter()
         .map(|line| line.trim_start_matches('/').to_string())
         .collect();
@@ -112,6 +110,11 @@ pub fn pkg_manifest_validate(config: &Config, pkg: &str, path: &str, manifest_pa
         .sum();
 
     if count != 0 {
-        die!(pkg, "manifest contains", count, "non-existent files");
+        return Err(KissError::InvalidPackage {
+            pkg: pkg.to_owned(),
+            reason: format!("manifest contains {} non-existent files", count),
+        });
     }
+
+    Ok(())
 }
@@ -0,0 +1,291 @@
+use crate::shared_lib::{
+    get_current_working_dir, globals::Config, read_a_dir_and_sort, read_a_files_lines,
+    resolve_path, KissError,
+};
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+// threading
+use crate::iter;
+#[cfg(feature = "threading")]
+use rayon::iter::ParallelIterator;
+
+// files of a single installed package and the mtime of its manifest
+struct IndexEntry {
+    mtime: u128,
+    files: Vec<String>,
+}
+
+// path -> package index over every installed manifest
+//
+// the index is cached in config.owners_db, entries of packages whose
+// manifest mtime changed since the cache was written are read again.
+// mtimes can be too coarse to notice a package installed in the same
+// second, so install and remove also update the package's entry with
+// update_owners_cache.
+pub struct OwnerIndex {
+    owners: HashMap<String, Vec<String>>,
+}
+
+impl OwnerIndex {
+    pub fn new(config: &Config) -> Result<Self, KissError> {
+        let mut cached: HashMap<String, IndexEntry> = read_cache(&config.owners_db);
+
+        let installed_packages: Vec<PathBuf> =
+            read_a_dir_and_sort(config.sys_db.to_string_lossy().to_string(), false, &[])?;
+
+        let packages: Vec<(String, u128)> = iter!(installed_packages)
+            .filter_map(|path| {
+                let name: String = path.file_name()?.to_string_lossy().to_string();
+                let mtime: u128 = manifest_mtime(&path.join("manifest"))?;
+                Some((name, mtime))
+            })
+            .collect();
+
+        let mut entries: HashMap<String, IndexEntry> = HashMap::new();
+        let mut stale: bool = cached.len() != packages.len();
+
+        for (pkg, mtime) in packages {
+            let entry: IndexEntry = match cached.remove(&pkg) {
+                Some(entry) if entry.mtime == mtime => entry,
+                _ => {
+                    stale = true;
+                    IndexEntry {
+                        mtime,
+                        files: manifest_files(config, &pkg),
+                    }
+                }
+            };
+            entries.insert(pkg, entry);
+        }
+
+        // ignore errors, the cache is only an optimization and it can not be
+        // written when running as a normal user
+        if stale {
+            let _ = write_cache(&config.owners_db, &entries);
+        }
+
+        let mut owners: HashMap<String, Vec<String>> = HashMap::new();
+        for (pkg, entry) in entries {
+            for file in entry.files {
+                owners.entry(file).or_default().push(pkg.to_owned());
+            }
+        }
+
+        Ok(OwnerIndex { owners })
+    }
+
+    // every package which lists path in its manifest
+    pub fn owners(&self, path: &str) -> &[String] {
+        self.owners.get(path).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    // first package other than skip which lists path in its manifest
+    pub fn owner(&self, path: &str, skip: Option<&str>) -> Option<&str> {
+        self.owners(path)
+            .iter()
+            .map(String::as_str)
+            .find(|owner| Some(*owner) != skip)
+    }
+}
+
+fn manifest_mtime(manifest: &Path) -> Option<u128> {
+    let modified = fs::metadata(manifest).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+// files in the installed manifest of pkg, directories can be shared
+fn manifest_files(config: &Config, pkg: &str) -> Vec<String> {
+    read_a_files_lines(config.sys_db.join(pkg).join("manifest"))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|line| !line.ends_with('/'))
+        .collect()
+}
+
+// replace the cached entry of pkg after it was installed or removed, other
+// entries are kept as they are
+pub fn update_owners_cache(config: &Config, pkg: &str) {
+    // nothing to update, the next query builds the whole cache
+    if !config.owners_db.exists() {
+        return;
+    }
+
+    let mut entries: HashMap<String, IndexEntry> = read_cache(&config.owners_db);
+
+    match manifest_mtime(&config.sys_db.join(pkg).join("manifest")) {
+        Some(mtime) => {
+            let files: Vec<String> = manifest_files(config, pkg);
+            entries.insert(pkg.to_owned(), IndexEntry { mtime, files });
+        }
+        None => {
+            entries.remove(pkg);
+        }
+    }
+
+    // a cache which can't be written is outdated, drop it
+    if write_cache(&config.owners_db, &entries).is_err() {
+        let _ = fs::remove_file(&config.owners_db);
+    }
+}
+
+// cache format:
+// @<pkg> <manifest mtime>
+// <file>
+// ...
+fn read_cache(path: &Path) -> HashMap<String, IndexEntry> {
+    let mut entries: HashMap<String, IndexEntry> = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for line in read_a_files_lines(path).unwrap_or_default() {
+        if let Some(header) = line.strip_prefix('@') {
+            current = None;
+            if let Some((pkg, mtime)) = header.split_once(' ') {
+                if let Ok(mtime) = mtime.parse::<u128>() {
+                    let files: Vec<String> = Vec::new();
+                    entries.insert(pkg.to_owned(), IndexEntry { mtime, files });
+                    current = Some(pkg.to_owned());
+                }
+            }
+        } else if let Some(entry) = current.as_ref().and_then(|pkg| entries.get_mut(pkg)) {
+            entry.files.push(line);
+        }
+    }
+
+    entries
+}
+
+fn write_cache(path: &Path, entries: &HashMap<String, IndexEntry>) -> Result<(), io::Error> {
+    // write to a tmp file first so readers never see a half written cache
+    let tmp_path: PathBuf = path.with_extension(format!("tmp-{}", std::process::id()));
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+    for (pkg, entry) in entries {
+        writeln!(writer, "@{} {}", pkg, entry.mtime)?;
+        for file in &entry.files {
+            writeln!(writer, "{}", file)?;
+        }
+    }
+
+    writer.flush()?;
+    fs::rename(tmp_path, path)?;
+
+    Ok(())
+}
+
+// find the installed package which owns the given file
+pub fn pkg_owner(config: &Config, path: &str) -> Result<Option<String>, KissError> {
+    Ok(OwnerIndex::new(config)?
+        .owner(path, None)
+        .map(|owner| owner.to_owned()))
+}
+
+// like pkg_owner but accepts any path to a file under KISS_ROOT
+pub fn pkg_owns(config: &Config, path: &str) -> Result<Option<String>, KissError> {
+    let path: PathBuf = if Path::new(path).is_absolute() {
+        PathBuf::from(path)
+    } else {
+        Path::new(&get_current_working_dir()).join(path)
+    };
+
+    // Strip 'KISS_ROOT' from the file path if passed.
+    let root: PathBuf = fs::canonicalize(&config.kiss_root).unwrap_or(config.kiss_root.clone());
+    let relative: &Path = path
+        .strip_prefix(&config.kiss_root)
+        .or_else(|_| path.strip_prefix(&root))
+        .unwrap_or(&path);
+    let file: String = format!("/{}", relative.to_string_lossy().trim_start_matches('/'));
+
+    let index: OwnerIndex = OwnerIndex::new(config)?;
+
+    if let Some(owner) = index.owner(&file, None) {
+        return Ok(Some(owner.to_owned()));
+    }
+
+    // follow symlinks in the parent directories
+    let resolved: Option<String> = resolve_path(config, &file).and_then(|resolved| {
+        let relative: &Path = resolved.strip_prefix(&root).ok()?;
+        Some(format!("/{}", relative.to_string_lossy()))
+    });
+
+    Ok(resolved.and_then(|resolved| index.owner(&resolved, None).map(|owner| owner.to_owned())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn test_config(name: &str) -> Config {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut config: Config = Config::new();
+        config.sys_db = dir.join("installed");
+        config.owners_db = dir.join("owners");
+        config
+    }
+
+    // write a manifest with a fixed mtime so a rewrite can keep it
+    fn write_manifest(config: &Config, pkg: &str, files: &[&str], mtime: u64) {
+        let dir: PathBuf = config.sys_db.join(pkg);
+        fs::create_dir_all(&dir).unwrap();
+
+        let manifest: File = File::create(dir.join("manifest")).unwrap();
+        let mut writer = BufWriter::new(&manifest);
+        for file in files {
+            writeln!(writer, "{}", file).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        manifest
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime))
+            .unwrap();
+    }
+
+    #[test]
+    fn only_changed_entries_are_reloaded() {
+        let config: Config = test_config("owners-reload");
+        write_manifest(&config, "a", &["/usr/bin/a", "/usr/bin/"], 1000);
+        write_manifest(&config, "b", &["/usr/bin/b", "/usr/bin/"], 1000);
+
+        let index: OwnerIndex = OwnerIndex::new(&config).unwrap();
+        assert_eq!(index.owners("/usr/bin/a"), ["a"]);
+        assert!(index.owners("/usr/bin/").is_empty());
+        assert!(config.owners_db.exists());
+
+        // a gets a new mtime, b is rewritten within the same timestamp
+        write_manifest(&config, "a", &["/usr/bin/a2"], 2000);
+        write_manifest(&config, "b", &["/usr/bin/b2"], 1000);
+
+        let index: OwnerIndex = OwnerIndex::new(&config).unwrap();
+        assert_eq!(index.owners("/usr/bin/a2"), ["a"]);
+        assert!(index.owners("/usr/bin/a").is_empty());
+        // still the cached entry
+        assert_eq!(index.owners("/usr/bin/b"), ["b"]);
+
+        // install and remove update the entry themselves
+        update_owners_cache(&config, "b");
+        let index: OwnerIndex = OwnerIndex::new(&config).unwrap();
+        assert_eq!(index.owners("/usr/bin/b2"), ["b"]);
+        assert!(index.owners("/usr/bin/b").is_empty());
+        assert_eq!(index.owners("/usr/bin/a2"), ["a"]);
+
+        fs::remove_dir_all(config.sys_db.join("b")).unwrap();
+        update_owners_cache(&config, "b");
+        assert!(!read_cache(&config.owners_db).contains_key("b"));
+        assert_eq!(
+            OwnerIndex::new(&config).unwrap().owner("/usr/bin/b2", None),
+            None
+        );
+
+        fs::remove_dir_all(config.sys_db.parent().unwrap()).unwrap();
+    }
+}
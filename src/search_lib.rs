@@ -1,17 +1,17 @@
 // global variables
 use crate::shared_lib::{read_a_dir_and_sort, read_a_files_lines};
+use crate::source_lib::Compression;
 
 use crate::shared_lib::globals::Config;
 
-// logging
-use crate::die;
-use crate::shared_lib::signal::pkg_clean;
+use crate::shared_lib::KissError;
 
 // threading
 use crate::iter;
 #[cfg(feature = "threading")]
 use rayon::iter::ParallelIterator;
 
+use std::ffi::OsStr;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -31,7 +31,7 @@ pub fn pkg_find_version(
 
     // Use Rayon to parallelize the iteration through kiss_path directories
     let found_packages: Vec<PathBuf> = iter!(kiss_path)
-        .flat_map(|path| read_a_dir_and_sort(path.as_str(), false, &[]))
+        .flat_map(|path| read_a_dir_and_sort(path.as_str(), false, &[]).unwrap_or_default())
         .filter(|package| {
             let package_name = match package.file_name() {
                 Some(file_name) => file_name.to_string_lossy(),
@@ -58,7 +58,7 @@ pub fn pkg_find_path(config: &Config, name: &str, search_path: Option<&String>)
 
     // Use Rayon to parallelize the iteration through kiss_path directories
     let found_packages: Vec<PathBuf> = iter!(kiss_path)
-        .flat_map(|path| read_a_dir_and_sort(path.as_str(), false, &[]))
+        .flat_map(|path| read_a_dir_and_sort(path.as_str(), false, &[]).unwrap_or_default())
         .filter(|package| {
             let package_name = match package.file_name() {
                 Some(file_name) => file_name.to_string_lossy(),
@@ -79,7 +79,7 @@ pub fn pkg_find_path(config: &Config, name: &str, search_path: Option<&String>)
 pub fn pkg_find(config: &Config, name: &str, version: bool, recursive: bool) {
     // Use Rayon to parallelize the iteration through kiss_path directories
     let found_packages: Vec<PathBuf> = iter!(config.kiss_path)
-        .flat_map(|path| read_a_dir_and_sort(path.as_str(), false, &[]))
+        .flat_map(|path| read_a_dir_and_sort(path.as_str(), false, &[]).unwrap_or_default())
         .filter(|package| {
             let package_name = match package.file_name() {
                 Some(file_name) => file_name.to_string_lossy(),
@@ -109,13 +109,7 @@ fn extract_package_version(package: &Path) -> Option<String> {
     let version_path: PathBuf = package.join("version");
 
     if version_path.exists() {
-        let mut version_lines: Vec<String> =
-            read_a_files_lines(&version_path).unwrap_or_else(|_| {
-                panic!(
-                    "Failed to read version file ({})",
-                    version_path.to_string_lossy()
-                )
-            });
+        let mut version_lines: Vec<String> = read_a_files_lines(&version_path).ok()?;
 
         if let Some(version_line) = version_lines.pop() {
             let mut version_parts = version_line.split_whitespace();
@@ -130,36 +124,42 @@ fn extract_package_version(package: &Path) -> Option<String> {
     None
 }
 
-pub fn pkg_cache(config: &Config, pkg: &str) -> Option<String> {
+pub fn pkg_cache(config: &Config, pkg: &str) -> Result<Option<String>, KissError> {
     let version: String =
-        pkg_find_version(config, pkg, None).unwrap_or_else(|| die!(pkg, "Failed to get version"));
-
-    let file: String = format!(
-        "{}/{}@{}.tar.",
-        config.bin_dir.to_string_lossy(),
-        pkg,
-        version
-    );
-    let file_with_ext: String = format!("{}{}", file, config.kiss_compress);
-
-    if Path::new(file_with_ext.as_str()).exists() {
-        return Some(file_with_ext);
-    } else {
-        for entry in fs::read_dir(&config.bin_dir).expect("Failed to read BIN_DIR") {
-            let entry = entry.unwrap();
-            let path = entry.path();
-
-            if path.is_file() {
-                if let Some(file_name) = path.file_name() {
-                    if let Some(file_name_str) = file_name.to_str() {
-                        if file_name_str.starts_with(file.as_str()) {
-                            return Some(file_name_str.to_owned());
-                        }
-                    }
-                }
-            }
-        }
+        pkg_find_version(config, pkg, None).ok_or_else(|| KissError::NotFound(pkg.to_owned()))?;
+
+    let prefix: String = format!("{}@{}.tar.", pkg, version);
+    let file_with_ext: PathBuf = config
+        .bin_dir
+        .join(format!("{}{}", prefix, config.kiss_compress));
+
+    if file_with_ext.is_file() {
+        return Ok(Some(file_with_ext.to_string_lossy().to_string()));
     }
 
-    None
+    // a tarball built with another KISS_COMPRESS, it is extracted by its
+    // contents but leftovers like .part files are not tarballs
+    let mut tarballs: Vec<PathBuf> = match fs::read_dir(&config.bin_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .file_name()
+                        .and_then(OsStr::to_str)
+                        .and_then(|name| name.strip_prefix(prefix.as_str()))
+                        .and_then(Compression::from_extension)
+                        .is_some()
+            })
+            .collect(),
+        // nothing was built yet
+        Err(_) => Vec::new(),
+    };
+    tarballs.sort();
+
+    if let Some(tarball) = tarballs.first() {
+        return Ok(Some(tarball.to_string_lossy().to_string()));
+    }
+
+    Ok(None)
 }
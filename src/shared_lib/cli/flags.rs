@@ -16,6 +16,19 @@ pub fn force_flag() -> Flag {
         .alias("f")
 }
 
+pub fn keep_logs_flag() -> Flag {
+    Flag::new("keep-logs", FlagType::Bool).description("keep build logs of successful builds")
+}
+
+pub fn as_user_flag() -> Flag {
+    Flag::new("as-user", FlagType::Bool)
+        .description("allow building as the invoking user when not running as root")
+}
+
+pub fn offline_flag() -> Flag {
+    Flag::new("offline", FlagType::Bool).description("never access the network")
+}
+
 pub fn prompt_flag() -> Flag {
     Flag::new("prompt", FlagType::Bool).description("disable prompts")
 }
@@ -42,12 +55,24 @@ pub fn pid_flag() -> Flag {
         .alias("pid")
 }
 
+pub fn build_user_flag() -> Flag {
+    Flag::new("build-user", FlagType::String).description(
+        "User(name or uid) to run build scripts as when running as root.(default: 1000)",
+    )
+}
+
 pub fn kiss_compress_flag() -> Flag {
     Flag::new("kiss-compress", FlagType::String)
         .description("Compression method to use for built package tarballs.(default: gz)")
         .alias("compress")
 }
 
+pub fn compress_level_flag() -> Flag {
+    Flag::new("compress-level", FlagType::Int).description(
+        "Compression level for built package tarballs.(default: depends on the method)",
+    )
+}
+
 pub fn kiss_root_flag() -> Flag {
     Flag::new("kiss-root", FlagType::String)
         .description("Where installed packages will go.(default: '/')")
@@ -76,6 +101,11 @@ pub fn kiss_path_flag() -> Flag {
         .alias("path")
 }
 
+pub fn download_jobs_flag() -> Flag {
+    Flag::new("download-jobs", FlagType::Int)
+        .description("Number of sources downloaded at the same time.(default: 4)")
+}
+
 // this depends on threading feature
 pub fn jobs_flag() -> Flag {
     #[cfg(feature = "threading")]
@@ -1,6 +1,6 @@
 use seahorse::Context;
 
-use crate::shared_lib::get_env_variable;
+use crate::shared_lib::{get_env_variable, KissError};
 use std::collections::HashSet;
 use std::path::PathBuf;
 
@@ -28,19 +28,37 @@ pub struct Config {
     pub choice: bool,
     pub debug: bool,
     pub force: bool,
+    pub keep_logs: bool,
+    pub as_user: bool,
+    pub offline: bool,
     pub prompt: bool,
     pub strip: bool,
     pub quiet: bool,
     pub verbose: bool,
     pub lvl: u8,
+    pub download_jobs: usize,
+    pub build_user: String,
     pub pid: u32,
     pub proc: PathBuf,
     // kiss_*
     pub kiss_cache_dir: PathBuf,
     pub kiss_compress: String,
+    // None uses the default level of kiss_compress
+    pub compress_level: Option<u32>,
+    // mtimes in package tarballs are clamped to it
+    pub source_date_epoch: Option<u64>,
     pub kiss_path: Vec<String>,
+    pub mirrors: Vec<String>,
+    pub binrepo: Vec<String>,
+    pub ca_bundle: Option<PathBuf>,
     pub kiss_root: PathBuf,
     pub kiss_tmp_dir: PathBuf,
+    // network
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Vec<String>,
+    pub connect_timeout: u64,
+    pub read_timeout: u64,
     // temoporary directories
     pub mak_dir: PathBuf,
     pub pkg_dir: PathBuf,
@@ -56,6 +74,7 @@ pub struct Config {
     pub pkg_db: String,
     pub sys_db: PathBuf,
     pub provides_db: PathBuf,
+    pub owners_db: PathBuf,
 }
 
 // implement a builder function
@@ -70,6 +89,24 @@ impl Config {
             PathBuf::from(env)
         };
         let kiss_compress: String = get_env_variable("KISS_COMPRESS", "gz".to_owned());
+        let compress_level: Option<u32> = {
+            let env: String = get_env_variable("KISS_COMPRESS_LEVEL", String::new());
+            match env.parse::<u32>() {
+                Ok(level) => Some(level),
+                Err(_) if env.is_empty() => None,
+                Err(_) => {
+                    eprintln!("WARNING: Ignoring invalid KISS_COMPRESS_LEVEL '{}'", env);
+                    None
+                }
+            }
+        };
+        let source_date_epoch: Option<u64> = get_env_variable("SOURCE_DATE_EPOCH", String::new())
+            .parse::<u64>()
+            .ok();
+        let build_user: String = get_env_variable("KISS_BUILD_USER", "1000".to_owned());
+        let keep_logs: bool = get_env_variable("KISS_KEEPLOG", "0".to_owned()) == "1";
+        let as_user: bool = get_env_variable("KISS_BUILD_AS_USER", "0".to_owned()) == "1";
+        let offline: bool = get_env_variable("KISS_OFFLINE", "0".to_owned()) == "1";
         let kiss_root: PathBuf = PathBuf::from(get_env_variable("KISS_ROOT", "/".to_owned()));
         let kiss_tmp_dir: PathBuf =
             PathBuf::from(get_env_variable("KISS_TMPDIR", format!("{}/kiss", cache)));
@@ -90,6 +127,7 @@ impl Config {
         let db: String = String::from("var/db/kiss");
         let provides_file_path: String = format!("{}/provides", db);
         let provides_db: PathBuf = kiss_root.join(provides_file_path);
+        let owners_db: PathBuf = kiss_root.join(format!("{}/owners", db));
         let cho_db: String = format!("{}/choices", db);
         let pkg_db: String = format!("{}/installed", db);
         let sys_db: PathBuf = kiss_root.join(&pkg_db);
@@ -111,22 +149,84 @@ impl Config {
             path
         };
 
+        // whitespace separated list of mirror urls
+        let mirrors: Vec<String> = get_env_variable("KISS_MIRRORS", String::new())
+            .split_whitespace()
+            .map(|mirror| mirror.to_owned())
+            .collect();
+
+        // whitespace separated list of binary repositories
+        let binrepo: Vec<String> = get_env_variable("KISS_BINREPO", String::new())
+            .split_whitespace()
+            .map(|repo| repo.to_owned())
+            .collect();
+
+        // proxies, the lowercase variables take precedence like in curl
+        let proxy = |name: &str| -> Option<String> {
+            let env: String =
+                get_env_variable(name, get_env_variable(&name.to_uppercase(), String::new()));
+            Some(env).filter(|proxy| !proxy.is_empty())
+        };
+        let http_proxy: Option<String> = proxy("http_proxy");
+        let https_proxy: Option<String> = proxy("https_proxy");
+        let no_proxy: Vec<String> = proxy("no_proxy")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_owned())
+            .filter(|host| !host.is_empty())
+            .collect();
+
+        // certificates trusted instead of the builtin ones
+        let ca_bundle: Option<PathBuf> = Some(get_env_variable("KISS_CA_BUNDLE", String::new()))
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
+        // timeouts in seconds, 0 disables them
+        let connect_timeout: u64 = get_env_variable("KISS_CONNECT_TIMEOUT", "30".to_owned())
+            .parse::<u64>()
+            .unwrap_or(30);
+        let read_timeout: u64 = get_env_variable("KISS_READ_TIMEOUT", "10".to_owned())
+            .parse::<u64>()
+            .unwrap_or(10);
+
+        let choice: bool = get_env_variable("KISS_CHOICE", "1".to_owned()) != "0";
+
+        // number of sources downloaded at the same time
+        let download_jobs: usize = get_env_variable("KISS_DOWNLOAD_JOBS", "4".to_owned())
+            .parse::<usize>()
+            .unwrap_or(4);
+
         Config {
-            choice: true,
+            choice,
             debug: false,
             force: false,
+            keep_logs,
+            as_user,
+            offline,
             prompt: true,
             strip: true,
             quiet: false,
             verbose: false,
             lvl: 1,
+            download_jobs,
+            build_user,
             pid,
             proc,
             kiss_cache_dir,
             kiss_compress,
+            compress_level,
+            source_date_epoch,
             kiss_path,
+            mirrors,
+            binrepo,
+            ca_bundle,
             kiss_root,
             kiss_tmp_dir,
+            http_proxy,
+            https_proxy,
+            no_proxy,
+            connect_timeout,
+            read_timeout,
             mak_dir,
             pkg_dir,
             tar_dir,
@@ -139,6 +239,7 @@ impl Config {
             pkg_db,
             sys_db,
             provides_db,
+            owners_db,
         }
     }
 }
@@ -155,7 +256,7 @@ pub fn get_config() -> RwLockReadGuard<'static, Config> {
     FLAG_CONTEXT.read().unwrap()
 }
 
-pub fn set_config(c: &Context, handle_signals: bool) {
+pub fn set_config(c: &Context, handle_signals: bool) -> Result<(), KissError> {
     let mut context: RwLockWriteGuard<'_, Config> = FLAG_CONTEXT.write().unwrap();
 
     #[cfg(feature = "threading")]
@@ -164,14 +265,25 @@ pub fn set_config(c: &Context, handle_signals: bool) {
             rayon::ThreadPoolBuilder::new()
                 .num_threads(jobs as usize)
                 .build_global()
-                .expect("Failed to build thread pool");
+                .map_err(|err| KissError::Other(format!("Failed to build thread pool: {}", err)))?;
         }
     }
 
     // bool flags
-    context.choice = !c.bool_flag("choice");
+    if c.bool_flag("choice") {
+        context.choice = false;
+    }
     context.debug = c.bool_flag("debug");
     context.force = c.bool_flag("force");
+    if c.bool_flag("keep-logs") {
+        context.keep_logs = true;
+    }
+    if c.bool_flag("as-user") {
+        context.as_user = true;
+    }
+    if c.bool_flag("offline") {
+        context.offline = true;
+    }
     context.prompt = !c.bool_flag("prompt");
     context.strip = !c.bool_flag("strip");
     context.quiet = c.bool_flag("quiet");
@@ -181,9 +293,23 @@ pub fn set_config(c: &Context, handle_signals: bool) {
         context.pid = pid as u32;
     }
 
+    if let Ok(download_jobs) = c.int_flag("download-jobs") {
+        context.download_jobs = download_jobs.max(1) as usize;
+    }
+
+    if let Ok(build_user) = c.string_flag("build-user") {
+        context.build_user = build_user;
+    }
+
     if let Ok(kiss_compress) = c.string_flag("kiss-compress") {
         context.kiss_compress = kiss_compress;
     }
+    if let Ok(compress_level) = c.int_flag("compress-level") {
+        let compress_level: u32 = u32::try_from(compress_level).map_err(|_| {
+            KissError::Unsupported(format!("Invalid compression level {}", compress_level))
+        })?;
+        context.compress_level = Some(compress_level);
+    }
 
     if let Ok(kiss_root) = c.string_flag("kiss-root") {
         context.kiss_root = PathBuf::from(kiss_root);
@@ -223,11 +349,13 @@ pub fn set_config(c: &Context, handle_signals: bool) {
     // setup signal handling
     if handle_signals {
         // create tmp dirs
-        create_tmp_dirs(&context);
+        create_tmp_dirs(&context)?;
         let handler = SigHandler::Handler(handle_sigint);
         unsafe {
-            signal(SIGINT, handler).unwrap();
-            signal(SIGTERM, handler).unwrap();
+            signal(SIGINT, handler).map_err(|err| KissError::Other(err.to_string()))?;
+            signal(SIGTERM, handler).map_err(|err| KissError::Other(err.to_string()))?;
         }
     }
+
+    Ok(())
 }
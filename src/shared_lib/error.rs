@@ -0,0 +1,221 @@
+use std::{fmt, io, path::PathBuf};
+
+// errors returned by the library, main.rs turns these into exit codes
+#[derive(Debug)]
+pub enum KissError {
+    // package could not be found in KISS_PATH or the binary cache
+    NotFound(String),
+    NotInstalled(String),
+    // (source, expected, actual) of every source whose checksum differs
+    ChecksumMismatch {
+        pkg: String,
+        mismatches: Vec<(String, String, String)>,
+    },
+    // number of sources needing a checksum and number of checksums
+    ChecksumCount {
+        pkg: String,
+        sources: usize,
+        checksums: usize,
+    },
+    BuildFailed {
+        pkg: String,
+        log: PathBuf,
+    },
+    // (path, owner) of every conflicting file, safe is set when the
+    // conflicts could have been converted to choices
+    Conflict {
+        pkg: String,
+        conflicts: Vec<(String, String)>,
+        safe: bool,
+    },
+    CircularDependency(Vec<String>),
+    // installed packages which depend on pkg
+    Dependents {
+        pkg: String,
+        rdepends: Vec<String>,
+    },
+    NotInstallable {
+        pkg: String,
+        missing: Vec<String>,
+    },
+    InvalidPackage {
+        pkg: String,
+        reason: String,
+    },
+    // installing failed after files were already changed, stage is the step
+    // which failed
+    DirtyFilesystem {
+        pkg: String,
+        stage: String,
+        source: io::Error,
+    },
+    // malformed line of a sources file
+    InvalidSources {
+        path: PathBuf,
+        line: usize,
+        reason: String,
+    },
+    // archive and the first entry of it which would be written outside of
+    // the extraction directory
+    UnsafeArchive {
+        archive: PathBuf,
+        entry: String,
+    },
+    // kiss lint found errors, every finding was printed already
+    LintFailed {
+        errors: usize,
+        warnings: usize,
+    },
+    // (pkg, error) of every download which failed
+    DownloadFailed(Vec<(String, KissError)>),
+    // (pkg, source) of every source which has to be downloaded in offline mode
+    MissingSources(Vec<(String, String)>),
+    PermissionDenied(String),
+    Unsupported(String),
+    Io(io::Error),
+    Git(git2::Error),
+    Http(Box<ureq::Error>),
+    Other(String),
+}
+
+impl fmt::Display for KissError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KissError::NotFound(pkg) => write!(f, "{}: Package not found", pkg),
+            KissError::NotInstalled(pkg) => write!(f, "{}: Not installed", pkg),
+            KissError::ChecksumMismatch { pkg, mismatches } => {
+                for (source, expected, actual) in mismatches {
+                    writeln!(f, "{}", source)?;
+                    writeln!(f, "  expected: {}", expected)?;
+                    writeln!(f, "  actual:   {}", actual)?;
+                }
+                write!(f, "{}: Checksum mismatch", pkg)
+            }
+            KissError::ChecksumCount {
+                pkg,
+                sources,
+                checksums,
+            } => write!(
+                f,
+                "{}: {} source(s) need a checksum but {} checksum(s) found, run 'kiss c {}'",
+                pkg, sources, checksums, pkg
+            ),
+            KissError::BuildFailed { pkg, log } => {
+                write!(f, "{}: Build failed, log saved to {}", pkg, log.display())
+            }
+            KissError::Conflict {
+                pkg,
+                conflicts,
+                safe,
+            } => {
+                for (path, owner) in conflicts {
+                    writeln!(f, "{} ({})", path, owner)?;
+                }
+                if *safe {
+                    write!(
+                        f,
+                        "{}: Package conflicts with another package, run 'KISS_CHOICE=1 kiss i {}' to add conflicts as alternatives",
+                        pkg, pkg
+                    )
+                } else {
+                    write!(
+                        f,
+                        "{}: Package conflicts with another package and can't be converted to choices",
+                        pkg
+                    )
+                }
+            }
+            KissError::CircularDependency(cycle) => {
+                write!(f, "Circular dependency detected: {}", cycle.join(" -> "))
+            }
+            KissError::Dependents { pkg, rdepends } => write!(
+                f,
+                "{}: Can't remove package, others depend on it: {}",
+                pkg,
+                rdepends.join(" ")
+            ),
+            KissError::NotInstallable { pkg, missing } => write!(
+                f,
+                "{}: Package not installable, missing {} package(s): {}",
+                pkg,
+                missing.len(),
+                missing.join(" ")
+            ),
+            KissError::InvalidPackage { pkg, reason } => write!(f, "{}: {}", pkg, reason),
+            KissError::DirtyFilesystem { pkg, stage, source } => write!(
+                f,
+                "{}: {}: {}, filesystem is now dirty, manual repair is needed",
+                pkg, stage, source
+            ),
+            KissError::InvalidSources { path, line, reason } => {
+                write!(f, "{}:{}: {}", path.display(), line, reason)
+            }
+            KissError::UnsafeArchive { archive, entry } => write!(
+                f,
+                "{}: Refusing to extract unsafe entry {}",
+                archive.display(),
+                entry
+            ),
+            KissError::LintFailed { errors, warnings } => {
+                write!(f, "{} error(s) and {} warning(s) found", errors, warnings)
+            }
+            KissError::DownloadFailed(failures) => {
+                for (pkg, err) in failures {
+                    writeln!(f, "{}: {}", pkg, err)?;
+                }
+                write!(f, "{} download(s) failed", failures.len())
+            }
+            KissError::MissingSources(sources) => {
+                for (pkg, source) in sources {
+                    writeln!(f, "{}: {}", pkg, source)?;
+                }
+                write!(
+                    f,
+                    "{} source(s) are missing and can't be downloaded in offline mode",
+                    sources.len()
+                )
+            }
+            KissError::PermissionDenied(message)
+            | KissError::Unsupported(message)
+            | KissError::Other(message) => write!(f, "{}", message),
+            KissError::Io(err) => write!(f, "{}", err),
+            KissError::Git(err) => write!(f, "{}", err),
+            KissError::Http(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for KissError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KissError::Io(err) | KissError::DirtyFilesystem { source: err, .. } => Some(err),
+            KissError::Git(err) => Some(err),
+            KissError::Http(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KissError {
+    fn from(err: io::Error) -> Self {
+        KissError::Io(err)
+    }
+}
+
+impl From<git2::Error> for KissError {
+    fn from(err: git2::Error) -> Self {
+        KissError::Git(err)
+    }
+}
+
+impl From<ureq::Error> for KissError {
+    fn from(err: ureq::Error) -> Self {
+        KissError::Http(Box::new(err))
+    }
+}
+
+impl From<std::path::StripPrefixError> for KissError {
+    fn from(err: std::path::StripPrefixError) -> Self {
+        KissError::Other(err.to_string())
+    }
+}
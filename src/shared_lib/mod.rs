@@ -1,14 +1,17 @@
 pub mod cli;
+pub mod error;
 pub mod logging;
 pub mod signal;
+pub mod sources;
 pub mod threading;
 
 // re-exports
 pub use self::cli::*;
-pub use crate::{die, log};
+pub use self::error::KissError;
+pub use self::sources::{read_sources, Source, SourceKind};
+pub use crate::log;
 
 use globals::Config;
-use signal::pkg_clean;
 
 use std::fs::File;
 use std::fs::{self, Metadata};
@@ -83,7 +86,7 @@ pub fn run_command(command: &str, args: &Vec<&str>) -> Result<ExitStatus> {
 #[inline]
 pub fn cat(path: &Path) -> Result<String> {
     let file_bytes: Vec<u8> = fs::read(path)?;
-    let buffer: String = String::from_utf8(file_bytes).unwrap_or(String::new());
+    let buffer: String = String::from_utf8(file_bytes).unwrap_or_default();
 
     Ok(buffer)
 }
@@ -104,13 +107,11 @@ pub fn read_a_files_lines(
 }
 
 #[inline]
-pub fn mkcd(folder_name: impl AsRef<Path> + AsRef<std::ffi::OsStr> + AsRef<str>) {
-    if let Err(err) = fs::create_dir_all(&folder_name) {
-        die!("Failed to create folder:", err);
-    }
-    if let Err(err) = env::set_current_dir(&folder_name) {
-        die!("Failed to change directory:", err);
-    }
+pub fn mkcd(folder_name: impl AsRef<Path> + AsRef<std::ffi::OsStr> + AsRef<str>) -> Result<()> {
+    fs::create_dir_all(&folder_name)?;
+    env::set_current_dir(&folder_name)?;
+
+    Ok(())
 }
 
 pub fn remove_chars_after_last(input: &str, ch: char) -> &str {
@@ -131,10 +132,7 @@ pub fn get_current_working_dir() -> String {
 pub fn get_directory_name(path: &str) -> &str {
     let path: &Path = Path::new(path);
     match path.file_name() {
-        Some(folder_name) => match folder_name.to_str() {
-            Some(name) => name,
-            None => "",
-        },
+        Some(folder_name) => folder_name.to_str().unwrap_or_default(),
         None => "",
     }
 }
@@ -177,22 +175,18 @@ pub fn read_a_dir_and_sort(
     path: impl AsRef<Path> + AsRef<std::ffi::OsStr> + AsRef<str>,
     recursive: bool,
     skip_ext: &[&str],
-) -> Vec<PathBuf> {
+) -> Result<Vec<PathBuf>> {
     let mut filtered_entries: Vec<PathBuf> = Vec::new();
 
     let folder_path: &Path = Path::new(&path);
 
     if folder_path.is_dir() {
-        let entries: Vec<_> = match fs::read_dir(folder_path) {
-            Ok(entries) => entries.collect(),
-            Err(e) => die!("Failed to read directory:", e),
-        };
+        let entries: Vec<_> = fs::read_dir(folder_path)?.collect();
 
         // Parallelize the directory traversal and filtering
         let filtered_entries_par: Vec<PathBuf> = iter!(entries)
             .filter_map(|entry| {
-                let entry = entry.as_ref().unwrap();
-                let path = entry.path();
+                let path = entry.as_ref().ok()?.path();
 
                 if !skip_ext.is_empty() {
                     if let Some(file_name) = path.file_name() {
@@ -224,7 +218,7 @@ pub fn read_a_dir_and_sort(
                         None
                     }
                 })
-                .collect();
+                .collect::<Result<Vec<Vec<PathBuf>>>>()?;
 
             for entries in subfolder_entries {
                 filtered_entries.extend(entries);
@@ -235,7 +229,7 @@ pub fn read_a_dir_and_sort(
     // Parallelize the sorting task
     sort!(filtered_entries);
 
-    filtered_entries
+    Ok(filtered_entries)
 }
 
 #[inline]
@@ -247,45 +241,17 @@ pub fn tmp_file(config: &Config, name: &str, suffix: &str) -> Result<(File, Path
     Ok((tmp_file, tmp_file_path))
 }
 
-#[inline]
-pub fn read_sources(
-    path: impl AsRef<Path> + AsRef<std::ffi::OsStr> + AsRef<str>,
-) -> Result<Vec<(String, String)>> {
-    let sources: Vec<String> = read_a_files_lines(path)?;
-
-    // filter out items that starts with ’#’, then return (source, dest)
-    let result: Vec<(String, String)> = iter!(sources)
-        .filter(|&x| !x.starts_with('#'))
-        .map(|source| {
-            // consider user-given folder name
-            if source.contains(' ') {
-                let source_parts: Vec<String> = source.split(' ').map(|l| l.to_owned()).collect();
-                (
-                    source_parts.first().unwrap().to_owned(),
-                    source_parts
-                        .last()
-                        .unwrap()
-                        .to_owned()
-                        .trim_end_matches('/')
-                        .to_owned(),
-                )
-            } else if !source.contains("git+") && !source.contains("http") {
-                (source.to_string(), source.to_string())
-            } else {
-                (source.to_string(), String::new())
-            }
-        })
-        .collect();
-
-    Ok(result)
-}
-
 #[inline]
 pub fn resolve_path(config: &Config, path: &str) -> Option<PathBuf> {
     let rpath: PathBuf = config.kiss_root.join(path.trim_start_matches('/'));
 
     let parent: &Path = rpath.parent()?;
 
+    // resolve symlinks in the parent directory (e.g. /lib -> /usr/lib) so a
+    // file reached through a symlinked directory is still seen as the same file
+    let canonical_parent: PathBuf = fs::canonicalize(parent).unwrap_or(parent.to_path_buf());
+    let parent: &Path = canonical_parent.as_path();
+
     let absolute_path: PathBuf = if parent.is_absolute() {
         parent
             .to_path_buf()
@@ -9,7 +9,7 @@ pub extern "C" fn handle_sigint(_signal: nix::libc::c_int) {
     process::exit(pkg_clean());
 }
 
-pub fn create_tmp_dirs(config: &Config) -> i32 {
+pub fn create_tmp_dirs(config: &Config) -> std::io::Result<()> {
     let dirs: Vec<&PathBuf> = vec![
         &config.sources_dir,
         &config.log_dir,
@@ -19,16 +19,13 @@ pub fn create_tmp_dirs(config: &Config) -> i32 {
         &config.tar_dir,
         &config.tmp_dir,
     ];
-    dirs.iter().for_each(|dir| {
+    for dir in dirs {
         if !dir.exists() {
-            if let Err(err) = fs::create_dir_all(dir) {
-                eprintln!("Failed to create directory: {}", err);
-                process::exit(1);
-            }
+            fs::create_dir_all(dir)?;
         }
-    });
+    }
 
-    0
+    Ok(())
 }
 
 pub fn pkg_clean() -> i32 {
@@ -0,0 +1,221 @@
+use crate::shared_lib::KissError;
+
+use std::fs;
+use std::path::{Component, Path};
+
+// flag at the end of a source which keeps a tarball from being extracted
+pub const NO_EXTRACT: &str = "?no-extract";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    // git+<url>[#commit|@branch-or-tag]
+    Git,
+    // <http(s) url>[|<fallback url>...]
+    Remote,
+    // file://<path> or an absolute path, with optional fallback urls
+    Local,
+    // file or directory relative to the package's repository
+    Relative,
+}
+
+// a line of a sources file
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub kind: SourceKind,
+    // the source as written, without flags
+    pub url: String,
+    // directory inside the build directory the source is put in, empty for
+    // the build directory itself
+    pub destination: String,
+    pub no_extract: bool,
+}
+
+impl Source {
+    // first url of a source with fallbacks, the file is named after it
+    pub fn primary(&self) -> &str {
+        self.url.split('|').next().unwrap_or(&self.url)
+    }
+}
+
+// parse a sources file, a package without one has no sources
+pub fn read_sources(path: impl AsRef<Path>) -> Result<Vec<Source>, KissError> {
+    let path: &Path = path.as_ref();
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content: String = fs::read_to_string(path)?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            parse_source(line)
+                .map_err(|reason| KissError::InvalidSources {
+                    path: path.to_path_buf(),
+                    line: index + 1,
+                    reason,
+                })
+                .transpose()
+        })
+        .collect()
+}
+
+// parse a line of a sources file, empty lines and comments are None
+pub fn parse_source(line: &str) -> Result<Option<Source>, String> {
+    let line: &str = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut fields = line.split_whitespace();
+    let source: &str = fields.next().unwrap_or_default();
+    let destination: &str = fields.next().unwrap_or_default().trim_end_matches('/');
+
+    if let Some(field) = fields.next() {
+        return Err(format!(
+            "Unexpected '{}', expected '<source> [destination]'",
+            field
+        ));
+    }
+
+    let (url, no_extract): (&str, bool) = match source.strip_suffix(NO_EXTRACT) {
+        Some(url) => (url, true),
+        None => (source, false),
+    };
+
+    let primary: &str = url.split('|').next().unwrap_or(url);
+    let fallbacks: Vec<&str> = url.split('|').skip(1).collect();
+
+    let kind: SourceKind = if let Some(git_url) = primary.strip_prefix("git+") {
+        if git_url.is_empty() {
+            return Err(String::from("Missing url after 'git+'"));
+        }
+        SourceKind::Git
+    } else if primary.starts_with("file://") || primary.starts_with('/') {
+        SourceKind::Local
+    } else if primary.contains("://") {
+        if !is_http_url(primary) {
+            return Err(format!("Unsupported url '{}', expected http(s)", primary));
+        }
+        SourceKind::Remote
+    } else {
+        SourceKind::Relative
+    };
+
+    if !fallbacks.is_empty() && matches!(kind, SourceKind::Git | SourceKind::Relative) {
+        return Err(String::from(
+            "Fallback urls are only supported for remote and local sources",
+        ));
+    }
+    if let Some(fallback) = fallbacks.iter().find(|fallback| !is_http_url(fallback)) {
+        return Err(format!(
+            "Invalid fallback url '{}', expected http(s)",
+            fallback
+        ));
+    }
+
+    // the destination must stay inside the build directory
+    if !Path::new(destination)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!(
+            "Destination '{}' must be a relative path without '..'",
+            destination
+        ));
+    }
+
+    Ok(Some(Source {
+        kind,
+        url: url.to_owned(),
+        destination: destination.to_owned(),
+        no_extract,
+    }))
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Source {
+        parse_source(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn comments_and_empty_lines() {
+        assert!(parse_source("").unwrap().is_none());
+        assert!(parse_source("   ").unwrap().is_none());
+        assert!(parse_source("# https://example.org/foo.tar.gz")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn remote_sources() {
+        let source: Source = parse("https://example.org/foo-1.0.tar.gz");
+        assert_eq!(source.kind, SourceKind::Remote);
+        assert_eq!(source.url, "https://example.org/foo-1.0.tar.gz");
+        assert_eq!(source.destination, "");
+        assert!(!source.no_extract);
+
+        let source: Source = parse("https://example.org/foo.tar.gz?no-extract vendor/");
+        assert_eq!(source.url, "https://example.org/foo.tar.gz");
+        assert_eq!(source.destination, "vendor");
+        assert!(source.no_extract);
+
+        assert!(parse_source("ftp://example.org/foo.tar.gz").is_err());
+        assert!(parse_source("https://example.org/foo.tar.gz dest extra").is_err());
+    }
+
+    #[test]
+    fn fallback_urls() {
+        let source: Source = parse("https://a.org/foo.tar.gz|https://b.org/foo.tar.gz");
+        assert_eq!(source.kind, SourceKind::Remote);
+        assert_eq!(source.primary(), "https://a.org/foo.tar.gz");
+
+        let source: Source = parse("file:///srv/foo.tar.gz|https://b.org/foo.tar.gz");
+        assert_eq!(source.kind, SourceKind::Local);
+        assert_eq!(source.primary(), "file:///srv/foo.tar.gz");
+
+        assert!(parse_source("https://a.org/foo.tar.gz|ftp://b.org/foo.tar.gz").is_err());
+        assert!(parse_source("git+https://a.org/foo|https://b.org/foo").is_err());
+        assert!(parse_source("patches/fix.patch|https://b.org/fix.patch").is_err());
+    }
+
+    #[test]
+    fn git_sources() {
+        let source: Source = parse("git+https://github.com/kisslinux/kiss@master");
+        assert_eq!(source.kind, SourceKind::Git);
+        assert_eq!(source.url, "git+https://github.com/kisslinux/kiss@master");
+
+        let source: Source = parse("git+https://github.com/kisslinux/kiss#0d2a8d3 kiss");
+        assert_eq!(source.kind, SourceKind::Git);
+        assert_eq!(source.destination, "kiss");
+
+        assert!(parse_source("git+").is_err());
+    }
+
+    #[test]
+    fn local_sources() {
+        assert_eq!(parse("/srv/dist/foo.tar.gz").kind, SourceKind::Local);
+        assert_eq!(parse("file:///srv/dist/foo.tar.gz").kind, SourceKind::Local);
+
+        let source: Source = parse("patches/fix.patch");
+        assert_eq!(source.kind, SourceKind::Relative);
+        assert_eq!(source.url, "patches/fix.patch");
+    }
+
+    #[test]
+    fn destinations() {
+        assert_eq!(parse("fix.patch ./patches/").destination, "./patches");
+        assert!(parse_source("fix.patch ../outside").is_err());
+        assert!(parse_source("fix.patch /absolute").is_err());
+    }
+}
@@ -1,6 +1,6 @@
-///
-/// whether to use rayon or not when using iter
-///
+//
+// whether to use rayon or not when using iter
+//
 
 // iter
 // choose which iter implementation to use
@@ -3,51 +3,48 @@ use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
-use git2::{AutotagOption, FetchOptions, RemoteCallbacks, Repository};
+use git2::{
+    AutotagOption, Commit, FetchOptions, ProxyOptions, Remote, RemoteCallbacks, Repository,
+};
 
 // logging functions
-use crate::shared_lib::signal::pkg_clean;
-use crate::{die, log};
-
-// thread
-use crate::iter;
-#[cfg(feature = "threading")]
-use rayon::iter::ParallelIterator;
+use crate::log;
 
+use crate::download_lib::{pkg_download_all, proxy_for_url, Download};
 use crate::search_lib::{pkg_find_path, pkg_find_version};
 
 use crate::shared_lib::globals::Config;
-use crate::shared_lib::{is_symlink, mkcd, read_sources, remove_chars_after_last, tmp_file};
+use crate::shared_lib::{
+    is_symlink, mkcd, read_a_files_lines, read_sources, remove_chars_after_last, KissError, Source,
+    SourceKind,
+};
 
 // tar
+use nix::unistd::{Group, User};
+use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::fs;
 use std::io::Read;
-use tar::{Archive, Builder, Header};
+use std::os::unix::fs::MetadataExt;
+use std::path::Component;
+use tar::{Archive, Builder, EntryType, Header};
+
+#[cfg(feature = "zip")]
+use std::os::unix::fs::PermissionsExt;
 
 #[cfg(feature = "bzip2")]
 use bzip2::{read::BzDecoder, write::BzEncoder};
 #[cfg(feature = "gzip")]
 use flate2::{read::GzDecoder, write::GzEncoder};
 #[cfg(feature = "lz4")]
-use lzzzz::lz4f::{ReadDecompressor, WriteCompressor};
+use lzzzz::lz4f::{PreferencesBuilder, ReadDecompressor, WriteCompressor};
+#[cfg(all(feature = "xz2", feature = "threading"))]
+use xz2::stream::MtStreamBuilder;
 #[cfg(feature = "xz2")]
 use xz2::{read::XzDecoder, write::XzEncoder};
 #[cfg(feature = "zstd")]
 use zstd::{stream::read::Decoder, stream::write::Encoder};
 
-// for http client
-use once_cell::sync::Lazy;
-use std::time::Duration;
-use ureq::{Agent, AgentBuilder, Response};
-
-// reusable lazy initialized HTTP CLIENT
-pub static HTTP_CLIENT: Lazy<Agent> = Lazy::new(|| {
-    AgentBuilder::new()
-        .timeout_read(Duration::from_secs(10))
-        .timeout_write(Duration::from_secs(10))
-        .build()
-});
-
 #[derive(PartialEq)]
 pub enum SourceType {
     Git {
@@ -58,8 +55,12 @@ pub enum SourceType {
         source: String,
         destination: PathBuf,
     },
+    // file:// or absolute path, copied into the sources cache
+    Local {
+        source: PathBuf,
+        destination: PathBuf,
+    },
     Cached(String),
-    Unknown,
 }
 
 // get root directories of repositories and return them as a vector
@@ -85,16 +86,15 @@ pub fn get_repositories(repo_path: &[String]) -> Vec<String> {
     repositories
 }
 
-pub fn pkg_update_repo(repo_path: &str) -> Result<(), git2::Error> {
+pub fn pkg_update_repo(config: &Config, repo_path: &str) -> Result<(), KissError> {
     let repository: Repository = Repository::open(repo_path)?;
 
-    let cb = setup_callbacks();
     let mut remote = repository
         .find_remote("origin")
         .or_else(|_| repository.remote_anonymous("origin"))?;
 
-    let mut fetch_options = FetchOptions::new();
-    fetch_options.remote_callbacks(cb);
+    let url: String = remote.url().unwrap_or_default().to_owned();
+    let mut fetch_options = fetch_options(config, &url)?;
     remote.fetch(&["origin"], Some(&mut fetch_options), None)?;
 
     {
@@ -129,6 +129,25 @@ pub fn pkg_update_repo(repo_path: &str) -> Result<(), git2::Error> {
     Ok(())
 }
 
+// fetch options using the proxy and CA bundle from config
+fn fetch_options<'a>(config: &'a Config, url: &str) -> Result<FetchOptions<'a>, git2::Error> {
+    if let Some(ca_bundle) = &config.ca_bundle {
+        // SAFETY: libgit2 only reads this when a tls connection is made, no
+        // fetch is running at this point
+        unsafe { git2::opts::set_ssl_cert_file(ca_bundle)? };
+    }
+
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(setup_callbacks());
+    if let Some(proxy) = proxy_for_url(config, url) {
+        let mut proxy_options = ProxyOptions::new();
+        proxy_options.url(proxy);
+        fo.proxy_options(proxy_options);
+    }
+
+    Ok(fo)
+}
+
 fn setup_callbacks() -> RemoteCallbacks<'static> {
     let mut cb = RemoteCallbacks::new();
     // This callback gets called for each remote-tracking branch that gets
@@ -175,97 +194,80 @@ pub fn pkg_source_resolve(
     config: &Config,
     package_name: &str,
     repo_dir: &str,
-    source: &String,
-    dest: &String,
+    source: &Source,
     print: bool,
-) -> SourceType {
-    let source_parts: Vec<String> = source.split('/').map(|e| e.to_owned()).collect();
-
-    // get last element- repo name - for git
-    let mut repo_name: String = source_parts.last().unwrap().to_owned();
-
-    // both git and remote sources uses this dest
-    let remote_dest: String = format!(
-        "{}/{}/{}{}",
-        config.sources_dir.to_string_lossy(),
-        package_name,
-        if !dest.is_empty() {
-            format!("{}/", dest)
-        } else {
-            dest.to_string()
+) -> Result<SourceType, KissError> {
+    // only the primary url of a source with fallbacks names the file
+    let primary: &str = source.primary();
+    let file_name: &str = match source.kind {
+        SourceKind::Git => git_cache_name(primary),
+        _ => primary.rsplit('/').next().unwrap_or(primary),
+    };
+
+    // git, remote and local sources all use this dest
+    let mut remote_dest: PathBuf = config.sources_dir.join(package_name);
+    if !source.destination.is_empty() {
+        remote_dest.push(&source.destination);
+    }
+    remote_dest.push(file_name);
+
+    let source_type: SourceType = match source.kind {
+        SourceKind::Git => SourceType::Git {
+            source: source.url.to_owned(),
+            destination: remote_dest,
         },
-        if &repo_name != dest && !repo_name.is_empty() {
-            if let Some(index) = repo_name.find('#') {
-                repo_name.truncate(index);
-            }
-            if let Some(index) = repo_name.find('@') {
-                repo_name.truncate(index);
-            }
-            repo_name
-        } else {
-            "".to_owned()
+        // remote or local source(cached)
+        SourceKind::Remote | SourceKind::Local if remote_dest.exists() => {
+            SourceType::Cached(remote_dest.to_string_lossy().to_string())
         }
-    );
-
-    let source_type: SourceType = match source {
-        // unwanted
-        _ if source.starts_with('#') => SourceType::Unknown,
-        // git source
-        _ if source.starts_with("git+") => SourceType::Git {
-            source: source.to_string(),
-            destination: PathBuf::from(remote_dest),
+        SourceKind::Remote => SourceType::Http {
+            source: source.url.to_owned(),
+            destination: remote_dest,
         },
-        // Remote source(cached)
-        _ if source.contains("://") && Path::new(&remote_dest).exists() => {
-            SourceType::Cached(remote_dest.to_string())
-        }
-        // remote source
-        _ if source.contains("://") => SourceType::Http {
-            source: source.to_string(),
-            destination: PathBuf::from(remote_dest),
+        SourceKind::Local => SourceType::Local {
+            source: PathBuf::from(primary.trim_start_matches("file://")),
+            destination: remote_dest,
         },
         // Local relative dir
-        _ if !repo_dir.is_empty()
-            && Path::new(repo_dir).join(source.as_str()).join(".").exists() =>
-        {
-            let source: String = format!("{}/{}/.", repo_dir, source);
-            SourceType::Cached(source)
+        SourceKind::Relative if Path::new(repo_dir).join(&source.url).is_dir() => {
+            SourceType::Cached(format!("{}/{}/.", repo_dir, source.url))
         }
         // Local relative file
-        _ if !repo_dir.is_empty() && Path::new(repo_dir).join(source.as_str()).exists() => {
-            let source = format!("{}/{}", repo_dir, source);
-            SourceType::Cached(source)
+        SourceKind::Relative if Path::new(repo_dir).join(&source.url).exists() => {
+            SourceType::Cached(format!("{}/{}", repo_dir, source.url))
+        }
+        SourceKind::Relative => {
+            return Err(KissError::Other(format!(
+                "{}: No local file: {}",
+                package_name, source.url
+            )));
         }
-        _ => SourceType::Unknown,
     };
 
-    match &source_type {
-        SourceType::Git {
-            source: res,
-            destination: _,
-        }
-        | SourceType::Http {
-            source: res,
-            destination: _,
-        } => {
-            if res.is_empty() {
-                die!(package_name, "No local file:", source);
-            }
+    if let SourceType::Cached(res) = &source_type {
+        if print && (config.debug || config.verbose) {
+            log!(package_name, "found", res);
         }
-        SourceType::Cached(res) => {
-            if print && (config.debug || config.verbose) {
-                log!(package_name, "found", res);
-            }
-        }
-        _ => {}
     }
 
-    source_type
+    Ok(source_type)
 }
 
-pub fn pkg_source(config: &Config, pkg: &str, skip_git: bool, print: bool) {
+// resolve the sources of pkg, fetch its git sources and return the remote
+// sources which still have to be downloaded
+//
+// in offline mode nothing is fetched, git sources which were never cloned
+// are returned as well. git sources which failed to fetch are added to
+// failures so they are reported together with failed downloads.
+pub fn pkg_source_downloads(
+    config: &Config,
+    pkg: &str,
+    skip_git: bool,
+    print: bool,
+    failures: &mut Vec<(String, KissError)>,
+) -> Result<Vec<Download>, KissError> {
     let repo_dir: String = pkg_find_path(config, pkg, None)
-        .unwrap_or_else(|| die!(pkg, "Failed to get package path"))
+        .ok_or_else(|| KissError::NotFound(pkg.to_owned()))?
         .to_string_lossy()
         .to_string();
 
@@ -275,93 +277,279 @@ pub fn pkg_source(config: &Config, pkg: &str, skip_git: bool, print: bool) {
 
     // Support packages without sources. Simply do nothing.
     if !sources_file.exists() {
-        return;
+        return Ok(Vec::new());
     }
 
     if config.debug || config.verbose {
         log!(repo_name, "Reading sources");
     }
 
-    let sources: Vec<(String, String)> = read_sources(sources_file.to_str().unwrap_or("sources"))
-        .expect("Failed to read sources file");
+    let sources: Vec<Source> = read_sources(&sources_file)?;
 
     // Support packages with empty sources file. Simply do nothing
+    let mut downloads: Vec<Download> = Vec::new();
 
-    iter!(sources).for_each(|(source, dest)| {
-        let source_type = pkg_source_resolve(
-            config,
-            repo_name.as_str(),
-            repo_dir.as_str(),
-            source,
-            dest,
-            print,
-        );
+    for line in sources.iter() {
+        let source_type = pkg_source_resolve(config, &repo_name, &repo_dir, line, print)?;
 
         match source_type {
             SourceType::Git {
                 source,
                 destination,
             } => {
-                mkcd(remove_chars_after_last(&destination.to_string_lossy(), '/'));
-                if !skip_git {
-                    if let Err(err) =
-                        pkg_source_git(&repo_name, &source, &destination.to_string_lossy(), true)
-                    {
-                        die!("Failed to fetch repository", err);
+                mkcd(remove_chars_after_last(&destination.to_string_lossy(), '/'))?;
+                if skip_git {
+                    continue;
+                }
+
+                if !config.offline {
+                    if let Err(err) = pkg_source_git(
+                        config,
+                        &repo_name,
+                        &source,
+                        &destination.to_string_lossy(),
+                        true,
+                    ) {
+                        log!(repo_name, "Failed to fetch:", source);
+                        failures.push((repo_name.to_owned(), err));
                     }
+                } else if !is_git_checkout(&destination) {
+                    downloads.push(Download {
+                        pkg: repo_name.to_owned(),
+                        source,
+                        destination,
+                    });
                 }
             }
-            SourceType::Http {
-                source,
+            SourceType::Local {
+                source: local_source,
                 destination,
             } => {
-                mkcd(remove_chars_after_last(&destination.to_string_lossy(), '/'));
-                if let Err(err) = pkg_source_url(config, &repo_name, &source, destination.as_path())
-                {
-                    die!("Failed to download file", err);
+                if local_source.is_file() {
+                    pkg_source_local(&repo_name, &local_source, &destination)?;
+                    continue;
+                }
+
+                // download the fallback urls if the file isn't there
+                match line.url.split_once('|') {
+                    Some((_, fallbacks)) => downloads.push(Download {
+                        pkg: repo_name.to_owned(),
+                        source: fallbacks.to_owned(),
+                        destination,
+                    }),
+                    None => {
+                        return Err(KissError::Other(format!(
+                            "{}: No local file: {}",
+                            repo_name,
+                            local_source.display()
+                        )))
+                    }
                 }
             }
+            SourceType::Http {
+                source,
+                destination,
+            } => downloads.push(Download {
+                pkg: repo_name.to_owned(),
+                source,
+                destination,
+            }),
 
             _ => {}
         }
-    });
+    }
+
+    Ok(downloads)
+}
+
+// copy a local source into the sources cache
+pub fn pkg_source_local(
+    package_name: &str,
+    source: &Path,
+    destination: &Path,
+) -> Result<(), KissError> {
+    log!(package_name, "Copying:", source.display());
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // copy next to the destination first so an interrupted copy is never
+    // seen as cached
+    let mut part_path: PathBuf = destination.to_path_buf().into_os_string().into();
+    part_path.as_mut_os_string().push(".part");
+
+    fs::copy(source, &part_path)?;
+    fs::rename(part_path, destination)?;
+
+    Ok(())
+}
+
+pub fn pkg_source(
+    config: &Config,
+    pkg: &str,
+    skip_git: bool,
+    print: bool,
+) -> Result<(), KissError> {
+    pkg_source_all(config, &[pkg], skip_git, print)
+}
+
+// like pkg_source but every download of every package shares one worker pool
+pub fn pkg_source_all<T: AsRef<str>>(
+    config: &Config,
+    packages: &[T],
+    skip_git: bool,
+    print: bool,
+) -> Result<(), KissError> {
+    let mut downloads: Vec<Download> = Vec::new();
+    let mut failures: Vec<(String, KissError)> = Vec::new();
+
+    for pkg in packages {
+        downloads.extend(pkg_source_downloads(
+            config,
+            pkg.as_ref(),
+            skip_git,
+            print,
+            &mut failures,
+        )?);
+    }
+
+    // list everything that is missing at once instead of failing on the first
+    if config.offline && !downloads.is_empty() {
+        return Err(missing_sources(downloads));
+    }
+
+    match pkg_download_all(config, downloads) {
+        Ok(()) => {}
+        Err(KissError::DownloadFailed(download_failures)) => failures.extend(download_failures),
+        Err(err) => return Err(err),
+    }
+
+    if !failures.is_empty() {
+        return Err(KissError::DownloadFailed(failures));
+    }
+
+    Ok(())
+}
+
+// fail with every source of packages which is not cached yet, used in
+// offline mode before anything is built
+//
+// only checks the cache, nothing is created, copied or fetched
+pub fn pkg_source_missing<T: AsRef<str>>(config: &Config, packages: &[T]) -> Result<(), KissError> {
+    let mut missing: Vec<(String, String)> = Vec::new();
+
+    for pkg in packages {
+        let pkg: &str = pkg.as_ref();
+        let repo_dir: String = pkg_find_path(config, pkg, None)
+            .ok_or_else(|| KissError::NotFound(pkg.to_owned()))?
+            .to_string_lossy()
+            .to_string();
+
+        let sources_file: PathBuf = Path::new(repo_dir.as_str()).join("sources");
+        if !sources_file.exists() {
+            continue;
+        }
+
+        for line in read_sources(&sources_file)? {
+            match pkg_source_resolve(config, pkg, &repo_dir, &line, false)? {
+                SourceType::Git {
+                    source,
+                    destination,
+                } if !is_git_checkout(&destination) => missing.push((pkg.to_owned(), source)),
+                // copied into the cache when building, no network needed
+                SourceType::Local { source, .. } if source.is_file() => {}
+                SourceType::Local { .. } | SourceType::Http { .. } => {
+                    missing.push((pkg.to_owned(), line.url.to_owned()))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(KissError::MissingSources(missing));
+    }
+
+    Ok(())
+}
+
+fn missing_sources(downloads: Vec<Download>) -> KissError {
+    KissError::MissingSources(
+        downloads
+            .into_iter()
+            .map(|download| (download.pkg, download.source))
+            .collect(),
+    )
+}
+
+// a git source which was cloned before and can be used without network access
+fn is_git_checkout(destination: &Path) -> bool {
+    Repository::open(destination)
+        .map(|repo| repo.head().is_ok())
+        .unwrap_or(false)
 }
 
 // Experimental Function to clone git repos
 // https://github.com/rust-lang/git2-rs/blob/master/examples/fetch.rs
 pub fn pkg_source_git(
+    config: &Config,
     package_name: &str,
-    source: &String,
+    source: &str,
     des: &str,
     log: bool,
-) -> Result<(), git2::Error> {
+) -> Result<(), KissError> {
     let repo: Repository = match Repository::open(des) {
         Ok(repo) => repo,
         Err(_) => Repository::init(des)?,
     };
-    let remote: &str = if !source.is_empty() {
-        source.trim_start_matches("git+")
-    } else {
-        "origin"
-    };
 
-    // Figure out whether it's a named remote or a URL
+    // Split the source into URL + OBJECT (branch or commit).
+    let (url, git_ref): (&str, Option<GitRef>) = split_git_source(source);
+
     if log {
-        log!(package_name, "Checking out:", remote);
+        log!(
+            package_name,
+            "Checking out:",
+            source.trim_start_matches("git+")
+        );
     }
-    let cb = setup_callbacks();
-    let mut remote = repo
-        .find_remote(remote)
-        .or_else(|_| repo.remote_anonymous(remote))?;
 
-    // Download the packfile and index it. This function updates the amount of
-    // received data and the indexer stats which lets you inform the user about
-    // progress.
-    let mut fo = FetchOptions::new();
-    fo.remote_callbacks(cb);
-    fo.prune(git2::FetchPrune::On);
-    fo.update_fetchhead(true);
-    remote.download(&[] as &[&str], Some(&mut fo))?;
+    if repo.find_remote("origin").is_ok() {
+        repo.remote_set_url("origin", url)?;
+    } else {
+        repo.remote("origin", url)?;
+    }
+    let mut remote = repo.find_remote("origin")?;
+
+    // Only fetch the requested branch or tag with a shallow clone, servers
+    // which can't do that get a full fetch. fetching a single commit isn't
+    // allowed by every server (and abbreviated hashes can't be fetched at
+    // all) so commits always fetch everything.
+    let refspecs: Vec<String> = match &git_ref {
+        Some(GitRef::Commit(_)) => Vec::new(),
+        Some(GitRef::Named(name)) => vec![
+            format!("+refs/heads/{0}:refs/remotes/origin/{0}", name),
+            format!("+refs/tags/{0}:refs/tags/{0}", name),
+        ],
+        None => vec![String::from("HEAD")],
+    };
+
+    let mut commit: Option<Commit> = None;
+    if !matches!(git_ref, Some(GitRef::Commit(_))) {
+        if git_fetch(config, url, &mut remote, &refspecs, 1).is_ok() {
+            commit = git_ref_commit(&repo, &git_ref);
+        }
+        if commit.is_none() && log {
+            log!(package_name, "Falling back to a full fetch");
+        }
+    }
+    if commit.is_none() {
+        // depth 0 leaves a shallow repository (from an earlier fetch) shallow
+        let depth: i32 = if repo.is_shallow() { i32::MAX } else { 0 };
+        git_fetch(config, url, &mut remote, &refspecs, depth)?;
+        commit = git_ref_commit(&repo, &git_ref);
+    }
 
     {
         // If there are local objects (we got a thin pack), then tell the user
@@ -386,172 +574,319 @@ pub fn pkg_source_git(
         }
     }
 
-    // Disconnect the underlying connection to prevent from idling.
-    remote.disconnect()?;
-
-    // Update the references in the remote's namespace to point to the right
-    // commits. This may be needed even if there was no packfile to download,
-    // which can happen e.g. when the branches have been changed but all the
-    // needed objects are available locally.
-    remote.update_tips(None, true, AutotagOption::Unspecified, None)?;
+    let commit: Commit = commit.ok_or_else(|| {
+        KissError::Other(match &git_ref {
+            Some(GitRef::Commit(commit)) => {
+                format!("{}: Commit {} not found in {}", package_name, commit, url)
+            }
+            Some(GitRef::Named(name)) => format!(
+                "{}: Branch or tag {} not found in {}",
+                package_name, name, url
+            ),
+            None => format!("{}: Nothing to check out from {}", package_name, url),
+        })
+    })?;
 
     // checkout fetched content
-    let reference = repo.find_reference("FETCH_HEAD")?;
-    let commit = reference.peel_to_commit()?;
     let mut checkout_builder = git2::build::CheckoutBuilder::new();
     checkout_builder.force();
     repo.checkout_tree(commit.as_object(), Some(&mut checkout_builder))?;
+    repo.set_head_detached(commit.id())?;
 
     Ok(())
 }
 
-// Function to download files
-pub fn pkg_source_url(
-    config: &Config,
-    repo_name: &String,
-    download_source: &String,
-    download_dest: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    log!(repo_name, "Downloading:", download_source);
-
-    let response: Response = HTTP_CLIENT.get(download_source).call()?;
-
-    let total_size: u64 = response
-        .header("Content-Length")
-        .and_then(|length| length.parse::<u64>().ok())
-        .unwrap_or(0);
-    let mut downloaded: u64 = 0;
-    let mut buffer: [u8; 8192] = [0; 8192];
-
-    // get file_name from download_dest variable
-    let file_name: String = download_dest
-        .to_string_lossy()
-        .split('/')
-        .last()
-        .unwrap()
-        .to_owned();
-
-    // tmp file
-    let (mut tmp_file, tmp_file_path) = tmp_file(config, file_name.as_str(), "download")?;
+// object a git source is pinned to
+#[derive(Debug, PartialEq, Eq)]
+enum GitRef {
+    // git+url#<commit>
+    Commit(String),
+    // git+url@<branch-or-tag>
+    Named(String),
+}
 
-    let mut response_reader = response.into_reader();
+// split git+url[#commit|@ref] into the url and the object at the last '#' or
+// '@' like kiss does, refs can contain slashes (@release/1.0). the host part
+// is skipped so user@host urls keep working
+fn split_git_source(source: &str) -> (&str, Option<GitRef>) {
+    let source: &str = source.trim_start_matches("git+");
+    let path_start: usize = match source.find("://") {
+        Some(index) => source[index + 3..]
+            .find('/')
+            .map_or(source.len(), |path| index + 3 + path),
+        // user@host:path
+        None => source.find(':').map_or(0, |index| index + 1),
+    };
 
-    while let Ok(bytes_read) = response_reader.read(&mut buffer) {
-        if bytes_read == 0 {
-            break;
+    match source[path_start..].rfind(['#', '@']) {
+        Some(index) => {
+            let (url, object) = source.split_at(path_start + index);
+            let git_ref: GitRef = match object.split_at(1) {
+                ("#", commit) => GitRef::Commit(commit.to_owned()),
+                (_, name) => GitRef::Named(name.to_owned()),
+            };
+            (url, Some(git_ref))
         }
+        None => (source, None),
+    }
+}
 
-        downloaded += bytes_read as u64;
+// name of the repository in the sources cache, the last path component of
+// the url without the commit, branch or tag
+fn git_cache_name(source: &str) -> &str {
+    let (url, _) = split_git_source(source);
+    url.rsplit(['/', ':']).next().unwrap_or(url)
+}
 
-        print_progress(downloaded, total_size);
+// commit a git source is pinned to, FETCH_HEAD if it isn't pinned
+fn git_ref_commit<'a>(repo: &'a Repository, git_ref: &Option<GitRef>) -> Option<Commit<'a>> {
+    let object = match git_ref {
+        Some(GitRef::Commit(commit)) => repo.revparse_single(commit).ok()?,
+        Some(GitRef::Named(name)) => repo
+            .find_reference(&format!("refs/remotes/origin/{}", name))
+            .or_else(|_| repo.find_reference(&format!("refs/tags/{}", name)))
+            .ok()?
+            .peel(git2::ObjectType::Commit)
+            .ok()?,
+        None => repo
+            .find_reference("FETCH_HEAD")
+            .ok()?
+            .peel(git2::ObjectType::Commit)
+            .ok()?,
+    };
 
-        tmp_file.write_all(&buffer[..bytes_read])?;
-    }
+    object.peel_to_commit().ok()
+}
 
-    println!("\rDownloading {}: 100% (Completed)", download_source);
+// fetch refspecs from remote, depth 0 fetches the whole history, i32::MAX
+// unshallows the repository and no refspecs fetch every branch and tag
+fn git_fetch<T: AsRef<str> + git2::IntoCString + Clone>(
+    config: &Config,
+    url: &str,
+    remote: &mut Remote,
+    refspecs: &[T],
+    depth: i32,
+) -> Result<(), git2::Error> {
+    // Download the packfile and index it. This function updates the amount of
+    // received data and the indexer stats which lets you inform the user about
+    // progress.
+    let mut fo = fetch_options(config, url)?;
+    fo.prune(git2::FetchPrune::On);
+    fo.update_fetchhead(true);
+    fo.depth(depth);
+    // only fetching everything needs the tags, they would end up in
+    // FETCH_HEAD otherwise
+    fo.download_tags(if refspecs.is_empty() {
+        AutotagOption::All
+    } else {
+        AutotagOption::None
+    });
 
-    // move tmp_file
-    std::fs::rename(tmp_file_path, download_dest).expect("Failed to move tmp_file");
+    // this also updates the references in the remote's namespace and
+    // disconnects
+    remote.fetch(refspecs, Some(&mut fo), None)
+}
 
-    Ok(())
+// owner of a file in a package tarball
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u64,
+    pub gid: u64,
+    // empty when the owner was given as a number
+    pub user: String,
+    pub group: String,
 }
 
-pub fn print_progress(progress: u64, total_size: u64) {
-    let formatted_progress: String = convert_bytes(progress);
-    if total_size == 0 {
-        print!("\rDownloading... ({}/Unknown)", formatted_progress);
-    } else {
-        let percent: f64 = (progress as f64 / total_size as f64) * 100.0;
-        let formatted_total_size: String = convert_bytes(total_size);
-        print!(
-            "\rDownloading... {:.2}% ({}/{})",
-            percent, formatted_progress, formatted_total_size
-        );
+impl Default for Owner {
+    fn default() -> Self {
+        Owner {
+            uid: 0,
+            gid: 0,
+            user: String::from("root"),
+            group: String::from("root"),
+        }
     }
-    io::stdout().flush().unwrap();
 }
 
-pub fn convert_bytes(bytes: u64) -> String {
-    const UNIT: u64 = 1024;
-    if bytes < UNIT {
-        return format!("{} B", bytes);
-    }
-    let exp: u32 = (bytes as f64).log(UNIT as f64) as u32;
-    let pre = "KMGTPE".chars().nth(exp as usize - 1).unwrap();
-    let value: f64 = bytes as f64 / f64::powi(UNIT as f64, exp as i32);
-    format!("{:.1} {}B", value, pre)
+// what ends up in the headers of a package tarball, everything else is taken
+// from the files so that rebuilding a package gives the same tarball
+#[derive(Debug, Clone, Default)]
+pub struct TarOptions {
+    // files which are not owned by root:root, relative to the package root
+    pub owners: HashMap<PathBuf, Owner>,
+    // SOURCE_DATE_EPOCH, newer mtimes are clamped to it
+    pub mtime: Option<u64>,
 }
 
+// add everything in dir to the tarball sorted by name, paths are relative to
+// root
 pub fn add_dirs_to_tar_recursive<W: Write>(
     builder: &mut Builder<W>,
+    root: &Path,
     dir: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
+    options: &TarOptions,
+) -> Result<(), KissError> {
     // Check if the provided path is a directory
     if !dir.is_dir() {
-        return Err("The provided path is not a directory.".into());
+        return Err(KissError::Other(format!(
+            "{} is not a directory",
+            dir.display()
+        )));
     }
 
-    let entries: Vec<_> = fs::read_dir(dir)?.collect();
-
-    for entry in entries {
-        let entry = entry?;
-        let entry_path: PathBuf = entry.path();
-        let rel_file_path: &Path = entry_path.strip_prefix(dir)?;
+    // read_dir order depends on the filesystem
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
 
+    for entry_path in entries {
+        let rel_file_path: &Path = entry_path.strip_prefix(root)?;
         // file_type.is_symlink() follows symlink and gives wrong results
-        if entry_path.is_symlink() || is_symlink(&entry_path) {
-            // If it's a symlink, get the symlink target as a String
-            let symlink_target: PathBuf = entry_path.read_link()?;
-            let symlink_target_str: &str = symlink_target
-                .to_str()
-                .ok_or("Invalid UTF-8 in symlink target")?;
+        let metadata: fs::Metadata = fs::symlink_metadata(&entry_path)?;
+
+        let mut header: Header = tar_header(&metadata, rel_file_path, options);
 
+        if metadata.file_type().is_symlink() {
             // Create the symlink in the tar with the same target
-            let mut header: Header = Header::new_ustar();
-            header.set_path(rel_file_path)?;
-            header.set_entry_type(tar::EntryType::Symlink);
-            header.set_link_name(symlink_target_str)?;
-            builder.append(&header, &mut io::empty())?;
-        } else if entry_path.is_dir() {
-            builder.append_dir_all(rel_file_path, &entry_path)?;
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, rel_file_path, entry_path.read_link()?)?;
+        } else if metadata.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, rel_file_path, io::empty())?;
+            add_dirs_to_tar_recursive(builder, root, &entry_path, options)?;
         } else {
-            let mut file = File::open(&entry_path)?;
-            builder.append_file(rel_file_path, &mut file)?;
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(metadata.len());
+            builder.append_data(&mut header, rel_file_path, File::open(&entry_path)?)?;
         }
     }
 
     Ok(())
 }
 
+// header with only the metadata kiss keeps, atime, ctime and the ownership
+// of the build are left out
+fn tar_header(metadata: &fs::Metadata, path: &Path, options: &TarOptions) -> Header {
+    let mut header: Header = Header::new_gnu();
+
+    let owner: Owner = options.owners.get(path).cloned().unwrap_or_default();
+    header.set_uid(owner.uid);
+    header.set_gid(owner.gid);
+    // names longer than the header allows are left out, the ids are enough
+    let _ = header.set_username(&owner.user);
+    let _ = header.set_groupname(&owner.group);
+
+    header.set_mode(metadata.mode() & 0o7777);
+
+    let mtime: u64 = metadata.mtime().max(0) as u64;
+    header.set_mtime(options.mtime.map_or(mtime, |epoch| mtime.min(epoch)));
+
+    header
+}
+
+// level to compress with, defaults to what the tools themselves use
+pub fn compress_level_for(compress_type: &str, level: Option<u32>) -> Result<u32, KissError> {
+    let (default, range): (u32, std::ops::RangeInclusive<u32>) = match compress_type {
+        "gz" => (6, 0..=9),
+        "bz2" => (6, 1..=9),
+        "lz4" => (0, 0..=12),
+        "xz" => (6, 0..=9),
+        "zst" => (3, 1..=22),
+        // unsupported types are reported by create_tar_archive
+        _ => return Ok(0),
+    };
+
+    match level {
+        None => Ok(default),
+        Some(level) if range.contains(&level) => Ok(level),
+        Some(level) => Err(KissError::Unsupported(format!(
+            "Compression level {} is out of range for {} ({}-{})",
+            level,
+            compress_type,
+            range.start(),
+            range.end()
+        ))),
+    }
+}
+
+// compression of KISS_COMPRESS, if kiss can create tarballs with it
+pub fn compression_for(compress_type: &str) -> Result<Compression, KissError> {
+    let compression: Compression = Compression::from_extension(compress_type).ok_or_else(|| {
+        KissError::Unsupported(format!(
+            "Unsupported compression type specified: {}",
+            compress_type
+        ))
+    })?;
+    compression.check_supported()?;
+
+    Ok(compression)
+}
+
 pub fn create_tar_archive(
     file: &str,
     compress_path: &Path,
     compress_type: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+    compress_level: Option<u32>,
+    options: &TarOptions,
+) -> Result<(), KissError> {
+    let level: u32 = compress_level_for(compress_type, compress_level)?;
+
+    // threads used by the xz and zstd encoders
+    #[cfg(feature = "threading")]
+    let threads: u32 = rayon::current_num_threads() as u32;
+
+    // check before creating the file so no empty tarball is left behind
+    let compression: Compression = compression_for(compress_type)?;
+
     // create tarball file
     let file: File = File::create(file)?;
 
     // encoder to use
     let encoder: Box<dyn Write> = match compress_type {
         #[cfg(feature = "gzip")]
-        "gz" => Box::new(GzEncoder::new(file, flate2::Compression::default())),
+        "gz" => Box::new(GzEncoder::new(file, flate2::Compression::new(level))),
         #[cfg(feature = "bzip2")]
-        "bz2" => Box::new(BzEncoder::new(file, bzip2::Compression::default())),
+        "bz2" => Box::new(BzEncoder::new(file, bzip2::Compression::new(level))),
         #[cfg(feature = "lz4")]
-        "lz4" => Box::new(WriteCompressor::new(file, Default::default())?),
-        #[cfg(feature = "xz2")]
-        "xz" => Box::new(XzEncoder::new(file, 6)),
-        // we use a BufWriter for zstd
+        "lz4" => Box::new(
+            WriteCompressor::new(
+                file,
+                PreferencesBuilder::new()
+                    .compression_level(level as i32)
+                    .build(),
+            )
+            .map_err(|err| KissError::Other(err.to_string()))?,
+        ),
+        #[cfg(all(feature = "xz2", not(feature = "threading")))]
+        "xz" => Box::new(XzEncoder::new(file, level)),
+        #[cfg(all(feature = "xz2", feature = "threading"))]
+        "xz" => {
+            let stream = MtStreamBuilder::new()
+                .preset(level)
+                .threads(threads)
+                .encoder()
+                .map_err(|err| KissError::Other(format!("Failed to create xz encoder: {}", err)))?;
+            Box::new(XzEncoder::new_stream(file, stream))
+        }
+        // the frame is only complete after finish(), which auto_finish calls
+        // on drop
         #[cfg(feature = "zstd")]
-        "zst" => Box::new(Encoder::new(file, 0)?),
-        _ => {
-            die!("Unsupported compression type specified.");
+        "zst" => {
+            #[allow(unused_mut)]
+            let mut encoder = Encoder::new(file, level as i32)?;
+            #[cfg(feature = "threading")]
+            encoder.multithread(threads)?;
+            Box::new(encoder.auto_finish())
         }
+        _ => return Err(compression.unsupported()),
     };
 
     // create compressed tar archive
     let mut builder = Builder::new(encoder);
-    add_dirs_to_tar_recursive(&mut builder, compress_path)?;
+    add_dirs_to_tar_recursive(&mut builder, compress_path, compress_path, options)?;
 
     builder.finish()?;
 
@@ -559,11 +894,11 @@ pub fn create_tar_archive(
 }
 
 // for creating tar archive
-pub fn pkg_tar(config: &Config, pkg: &str) {
+pub fn pkg_tar(config: &Config, pkg: &str) -> Result<(), KissError> {
     log!(pkg, "Creating tarball");
 
     let pkg_ver: String =
-        pkg_find_version(config, pkg, None).unwrap_or_else(|| die!(pkg, "Failed to get version"));
+        pkg_find_version(config, pkg, None).ok_or_else(|| KissError::NotFound(pkg.to_owned()))?;
     let tar_file: String = format!(
         "{}/{}@{}.tar.{}",
         config.bin_dir.to_string_lossy(),
@@ -573,59 +908,894 @@ pub fn pkg_tar(config: &Config, pkg: &str) {
     );
     let pkg_dir: PathBuf = config.pkg_dir.join(pkg);
 
-    if let Err(err) = create_tar_archive(tar_file.as_str(), &pkg_dir, config.kiss_compress.as_str())
-    {
-        die!("Failed to create tarball:", err);
-    } else {
-        log!(pkg, "Successfully created tarball");
+    let options: TarOptions = TarOptions {
+        owners: pkg_ownership(config, pkg, &pkg_dir)?,
+        mtime: config.source_date_epoch,
+    };
+
+    create_tar_archive(
+        tar_file.as_str(),
+        &pkg_dir,
+        config.kiss_compress.as_str(),
+        config.compress_level,
+        &options,
+    )?;
+    log!(pkg, "Successfully created tarball");
+
+    Ok(())
+}
+
+// read the package's ownership file, lines are '<path> <user>:<group>'
+// where user and group are names or ids
+fn pkg_ownership(
+    config: &Config,
+    pkg: &str,
+    pkg_dir: &Path,
+) -> Result<HashMap<PathBuf, Owner>, KissError> {
+    let ownership_file: PathBuf = pkg_dir.join(&config.pkg_db).join(pkg).join("ownership");
+    let mut owners: HashMap<PathBuf, Owner> = HashMap::new();
+
+    for (index, line) in read_a_files_lines(&ownership_file)?.iter().enumerate() {
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |reason: String| KissError::InvalidPackage {
+            pkg: pkg.to_owned(),
+            reason: format!("ownership:{}: {}", index + 1, reason),
+        };
+
+        let (path, owner): (&str, &str) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [path, owner] => (path, owner),
+            _ => return Err(invalid(String::from("Expected '<path> <user>:<group>'"))),
+        };
+        let (user, group): (&str, &str) = owner
+            .split_once(':')
+            .ok_or_else(|| invalid(format!("Expected '<user>:<group>', found '{}'", owner)))?;
+
+        let path: PathBuf = PathBuf::from(path.trim_start_matches('/'));
+        if !is_symlink(&pkg_dir.join(&path)) && !pkg_dir.join(&path).exists() {
+            return Err(invalid(format!(
+                "/{} is not in the package",
+                path.display()
+            )));
+        }
+
+        let (uid, user): (u64, String) = match user.parse::<u32>() {
+            Ok(uid) => (uid.into(), String::new()),
+            Err(_) => match User::from_name(user) {
+                Ok(Some(found)) => (found.uid.as_raw().into(), user.to_owned()),
+                _ => return Err(invalid(format!("Unknown user '{}'", user))),
+            },
+        };
+        let (gid, group): (u64, String) = match group.parse::<u32>() {
+            Ok(gid) => (gid.into(), String::new()),
+            Err(_) => match Group::from_name(group) {
+                Ok(Some(found)) => (found.gid.as_raw().into(), group.to_owned()),
+                _ => return Err(invalid(format!("Unknown group '{}'", group))),
+            },
+        };
+
+        owners.insert(
+            path,
+            Owner {
+                uid,
+                gid,
+                user,
+                group,
+            },
+        );
     }
+
+    Ok(owners)
 }
 
 // for extracting
-pub fn pkg_source_tar(res: &String, extract_path: &Path, no_leading_dir: bool) {
-    let file: File = File::open(res).expect("Failed to open tar file");
-    let extension: Option<&str> = Path::new(res.as_str())
-        .extension()
-        .and_then(|ext| ext.to_str());
-    let mut decoder: Box<dyn Read> = match extension {
-        #[cfg(feature = "gzip")]
-        Some("gz") => Box::new(GzDecoder::new(file)),
-        #[cfg(feature = "bzip2")]
-        Some("bz2") => Box::new(BzDecoder::new(file)),
-        #[cfg(feature = "lz4")]
-        Some("lz4") => {
-            Box::new(ReadDecompressor::new(file).expect("Failed to decompress tar.lz4 archive"))
+// compression of a tarball, detected from its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Lz4,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_magic(magic: &[u8]) -> Self {
+        match magic {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            [0x04, 0x22, 0x4d, 0x18, ..] => Compression::Lz4,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            _ => Compression::None,
         }
-        #[cfg(feature = "xz2")]
-        Some("xz") => Box::new(XzDecoder::new(file)),
-        #[cfg(feature = "zstd")]
-        Some("zst") => Box::new(Decoder::new(file).expect("Failed to decompress tar.zst archive")),
-        _ => return,
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "uncompressed",
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Lz4 => "lz4",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    // extension of a tarball compressed with it, as in KISS_COMPRESS
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "gz" => Some(Compression::Gzip),
+            "bz2" => Some(Compression::Bzip2),
+            "lz4" => Some(Compression::Lz4),
+            "xz" => Some(Compression::Xz),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // cargo feature kiss needs to be built with to use it
+    fn feature(self) -> &'static str {
+        match self {
+            Compression::Xz => "xz2",
+            _ => self.name(),
+        }
+    }
+
+    pub fn decoder(self, file: File) -> Result<Box<dyn Read>, KissError> {
+        match self {
+            Compression::None => Ok(Box::new(file)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(GzDecoder::new(file))),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Ok(Box::new(BzDecoder::new(file))),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(Box::new(
+                ReadDecompressor::new(file).map_err(|err| KissError::Other(err.to_string()))?,
+            )),
+            #[cfg(feature = "xz2")]
+            Compression::Xz => Ok(Box::new(XzDecoder::new(file))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(Decoder::new(file)?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    // error if kiss was built without the feature it needs
+    pub fn check_supported(self) -> Result<(), KissError> {
+        match self {
+            Compression::None => Ok(()),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(()),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Ok(()),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(()),
+            #[cfg(feature = "xz2")]
+            Compression::Xz => Ok(()),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(()),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    fn unsupported(self) -> KissError {
+        KissError::Unsupported(format!(
+            "{} compression needs kiss to be built with the '{}' feature",
+            self.name(),
+            self.feature()
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar(Compression),
+    Zip,
+}
+
+// kind of archive a file is from its contents, None if it isn't one
+//
+// a compressed file is only a tarball if the name says so or there is a tar
+// header after decompressing it, a compressed patch is not extracted.
+pub fn archive_kind(path: &Path) -> Result<Option<ArchiveKind>, KissError> {
+    let mut header: Vec<u8> = Vec::with_capacity(512);
+    File::open(path)?.take(512).read_to_end(&mut header)?;
+
+    if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+        return Ok(Some(ArchiveKind::Zip));
+    }
+
+    let compression: Compression = Compression::from_magic(&header);
+
+    // an uncompressed tarball has a tar header whatever its name is
+    if compression == Compression::None {
+        return Ok(is_tar_header(&header).then_some(ArchiveKind::Tar(compression)));
+    }
+    if has_tar_name(path) {
+        return Ok(Some(ArchiveKind::Tar(compression)));
+    }
+
+    // something kiss can't decompress is not a tarball we know about
+    let mut header: Vec<u8> = Vec::with_capacity(512);
+    let read: bool = compression
+        .decoder(File::open(path)?)
+        .map(|decoder| decoder.take(512).read_to_end(&mut header).is_ok())
+        .unwrap_or(false);
+    if !read {
+        return Ok(None);
+    }
+
+    Ok(is_tar_header(&header).then_some(ArchiveKind::Tar(compression)))
+}
+
+fn is_tar_header(header: &[u8]) -> bool {
+    header.get(257..262) == Some(b"ustar")
+}
+
+fn has_tar_name(path: &Path) -> bool {
+    let name: String = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    name.ends_with(".tar")
+        || name.contains(".tar.")
+        || [".tgz", ".tbz", ".tbz2", ".txz", ".tzst", ".tlz4"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+// extract a tarball or zip archive into extract_path
+//
+// with strip_leading_dir the top-level directory of the archive is left
+// out, as long as every file is inside that one directory. with preserve
+// tarballs keep their owners and setuid/setgid bits, which needs root.
+pub fn pkg_source_tar(
+    res: &str,
+    extract_path: &Path,
+    strip_leading_dir: bool,
+    preserve: bool,
+) -> Result<(), KissError> {
+    let path: &Path = Path::new(res);
+
+    // a compressed file kiss was built without support for
+    let mut magic: Vec<u8> = Vec::with_capacity(6);
+    File::open(path)?.take(6).read_to_end(&mut magic)?;
+    if let Err(err) = Compression::from_magic(&magic).decoder(File::open(path)?) {
+        return Err(KissError::Unsupported(format!("{}: {}", res, err)));
+    }
+
+    match archive_kind(path)? {
+        Some(ArchiveKind::Tar(compression)) => {
+            extract_tar(path, compression, extract_path, strip_leading_dir, preserve)
+        }
+        #[cfg(feature = "zip")]
+        Some(ArchiveKind::Zip) => extract_zip(path, extract_path, strip_leading_dir),
+        #[cfg(not(feature = "zip"))]
+        Some(ArchiveKind::Zip) => Err(KissError::Unsupported(format!(
+            "{}: zip archives need kiss to be built with the 'zip' feature",
+            res
+        ))),
+        None => Err(KissError::Unsupported(format!(
+            "{}: Not a tarball or zip archive",
+            res
+        ))),
+    }
+}
+
+fn extract_tar(
+    path: &Path,
+    compression: Compression,
+    extract_path: &Path,
+    strip_leading_dir: bool,
+    preserve: bool,
+) -> Result<(), KissError> {
+    let open = || -> Result<Archive<Box<dyn Read>>, KissError> {
+        let mut archive: Archive<Box<dyn Read>> =
+            Archive::new(compression.decoder(File::open(path)?)?);
+        archive.set_preserve_ownerships(preserve);
+        archive.set_preserve_permissions(preserve);
+        Ok(archive)
+    };
+    let read_error =
+        |err: io::Error| KissError::Other(format!("Failed to read {}: {}", path.display(), err));
+    let extract_error = |entry: &Path, err: io::Error| {
+        KissError::Other(format!(
+            "Failed to extract {} from {}: {}",
+            entry.display(),
+            path.display(),
+            err
+        ))
     };
 
-    let mut archive: Archive<&mut Box<dyn std::io::Read>> = Archive::new(&mut decoder);
+    // tarballs can only be read from the start, so listing the top-level
+    // directories needs a pass of its own
+    let leading_dir: Option<PathBuf> = if strip_leading_dir {
+        let mut entries: Vec<(PathBuf, bool)> = Vec::new();
+        for entry in open()?.entries().map_err(read_error)? {
+            let entry = entry.map_err(read_error)?;
+            let entry_type = entry.header().entry_type();
+            if !entry_type.is_pax_global_extensions() {
+                entries.push((entry.path()?.into_owned(), entry_type.is_dir()));
+            }
+        }
+        leading_dir(&entries)
+    } else {
+        None
+    };
 
-    // extract contents of tar directly to current dir
-    for entry in archive.entries().unwrap() {
-        let mut entry = entry.unwrap();
-        let path = entry.path().unwrap();
+    // an entry which would end up outside of extract_path stops the
+    // extraction
+    let unsafe_entry = |entry: String| KissError::UnsafeArchive {
+        archive: path.to_path_buf(),
+        entry,
+    };
 
-        // remove first level directory from dest
-        let dest_path: PathBuf = if !no_leading_dir {
-            extract_path.join(path)
-        } else {
-            extract_path.join(
-                path.components()
-                    .skip(1)
-                    .collect::<std::path::PathBuf>()
-                    .to_string_lossy()
-                    .to_string(),
+    for entry in open()?.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_pax_global_extensions() {
+            continue;
+        }
+
+        let entry_path: PathBuf = entry.path()?.into_owned();
+        if !is_safe_path(&entry_path) {
+            return Err(unsafe_entry(entry_path.display().to_string()));
+        }
+
+        let stripped: PathBuf = match strip_path(&entry_path, leading_dir.as_deref()) {
+            Some(stripped) => stripped,
+            None => continue,
+        };
+        // a directory entry would change the permissions of the symlink's target
+        if through_symlink(extract_path, &stripped, entry_type.is_dir()) {
+            return Err(unsafe_entry(format!(
+                "{} (through a symlink)",
+                entry_path.display()
+            )));
+        }
+        let dest_path: PathBuf = extract_path.join(stripped);
+
+        // hard links point to another path in the archive
+        if entry_type.is_hard_link() {
+            let link: Option<PathBuf> = entry.link_name()?.map(|link| link.into_owned());
+            let target: Option<PathBuf> = link
+                .as_deref()
+                .filter(|link| is_safe_path(link))
+                .and_then(|link| strip_path(link, leading_dir.as_deref()))
+                .filter(|target| !through_symlink(extract_path, target, false));
+
+            let target: PathBuf = match target {
+                Some(target) => target,
+                None => {
+                    return Err(unsafe_entry(format!(
+                        "{} -> {}",
+                        entry_path.display(),
+                        link.unwrap_or_default().display()
+                    )))
+                }
+            };
+
+            if let Some(parent) = dest_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let _ = fs::remove_file(&dest_path);
+            fs::hard_link(extract_path.join(target), &dest_path)
+                .map_err(|err| extract_error(&entry_path, err))?;
+            continue;
+        }
+
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        entry
+            .unpack(&dest_path)
+            .map_err(|err| extract_error(&entry_path, err))?;
+
+        // tar only sets the owner of files and directories
+        if preserve && entry_type.is_symlink() {
+            let header: &Header = entry.header();
+            std::os::unix::fs::lchown(
+                &dest_path,
+                Some(header.uid()? as u32),
+                Some(header.gid()? as u32),
             )
+            .map_err(|err| extract_error(&entry_path, err))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "zip")]
+fn extract_zip(path: &Path, extract_path: &Path, strip_leading_dir: bool) -> Result<(), KissError> {
+    let zip_error =
+        |err: zip::result::ZipError| KissError::Other(format!("{}: {}", path.display(), err));
+
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(zip_error)?;
+
+    let leading_dir: Option<PathBuf> = if strip_leading_dir {
+        let entries: Vec<(PathBuf, bool)> = archive
+            .file_names()
+            .map(|name| (PathBuf::from(name), name.ends_with('/')))
+            .collect();
+        leading_dir(&entries)
+    } else {
+        None
+    };
+
+    let unsafe_entry = |entry: String| KissError::UnsafeArchive {
+        archive: path.to_path_buf(),
+        entry,
+    };
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(zip_error)?;
+
+        let entry_path: PathBuf = match file.enclosed_name() {
+            Some(entry_path) => entry_path.to_path_buf(),
+            None => return Err(unsafe_entry(file.name().to_owned())),
+        };
+        let stripped: PathBuf = match strip_path(&entry_path, leading_dir.as_deref()) {
+            Some(stripped) => stripped,
+            None => continue,
         };
+        if through_symlink(extract_path, &stripped, file.is_dir()) {
+            return Err(unsafe_entry(format!(
+                "{} (through a symlink)",
+                entry_path.display()
+            )));
+        }
+        let dest_path: PathBuf = extract_path.join(stripped);
 
-        if let Err(err) = entry.unpack(dest_path) {
-            eprintln!("Failed to extract file: {}", err);
+        if file.is_dir() {
+            fs::create_dir_all(&dest_path)?;
             continue;
         }
+
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // File::create would write to the symlink's target
+        if is_symlink(&dest_path) {
+            fs::remove_file(&dest_path)?;
+        }
+
+        let mut dest_file: File = File::create(&dest_path)?;
+        io::copy(&mut file, &mut dest_file)?;
+
+        if let Some(mode) = file.unix_mode() {
+            fs::set_permissions(&dest_path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+
+    Ok(())
+}
+
+// archive entries with '..' or absolute paths escape the extraction directory
+fn is_safe_path(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+// whether writing to path, relative to extract_path, would follow a symlink
+// which is already there. the path itself is only checked with include_self,
+// files replace a symlink instead of following it.
+fn through_symlink(extract_path: &Path, path: &Path, include_self: bool) -> bool {
+    let components: Vec<Component> = path
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect();
+    let count: usize = if include_self {
+        components.len()
+    } else {
+        components.len().saturating_sub(1)
+    };
+
+    let mut current: PathBuf = extract_path.to_path_buf();
+    components[..count].iter().any(|component| {
+        current.push(component);
+        is_symlink(&current)
+    })
+}
+
+// the directory every entry of an archive is in, if there is exactly one
+fn leading_dir(entries: &[(PathBuf, bool)]) -> Option<PathBuf> {
+    let mut leading_dir: Option<&OsStr> = None;
+
+    for (path, is_dir) in entries {
+        let mut components = path.components().filter(|c| *c != Component::CurDir);
+
+        let first: &OsStr = match components.next() {
+            Some(Component::Normal(first)) => first,
+            // './' itself
+            None => continue,
+            _ => return None,
+        };
+
+        // a file next to the directory
+        if components.next().is_none() && !is_dir {
+            return None;
+        }
+
+        match leading_dir {
+            Some(dir) if dir != first => return None,
+            _ => leading_dir = Some(first),
+        }
+    }
+
+    leading_dir.map(PathBuf::from)
+}
+
+// path of an archive entry without the leading directory, None for the
+// leading directory itself
+fn strip_path(path: &Path, leading_dir: Option<&Path>) -> Option<PathBuf> {
+    let path: PathBuf = path
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect();
+
+    let path: PathBuf = match leading_dir {
+        Some(dir) => path.strip_prefix(dir).ok()?.to_path_buf(),
+        None => path,
+    };
+
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_source_refs() {
+        let named = |name: &str| Some(GitRef::Named(name.to_owned()));
+        let commit = |commit: &str| Some(GitRef::Commit(commit.to_owned()));
+
+        assert_eq!(
+            split_git_source("git+https://github.com/kisslinux/kiss"),
+            ("https://github.com/kisslinux/kiss", None)
+        );
+        assert_eq!(
+            split_git_source("git+https://github.com/kisslinux/kiss#0d2a8d3"),
+            ("https://github.com/kisslinux/kiss", commit("0d2a8d3"))
+        );
+        assert_eq!(
+            split_git_source("git+https://github.com/kisslinux/kiss@master"),
+            ("https://github.com/kisslinux/kiss", named("master"))
+        );
+        assert_eq!(
+            split_git_source("git+https://host/repo@release/1.0"),
+            ("https://host/repo", named("release/1.0"))
+        );
+    }
+
+    #[test]
+    fn git_source_user_at_host() {
+        assert_eq!(
+            split_git_source("git+ssh://git@host/repo"),
+            ("ssh://git@host/repo", None)
+        );
+        assert_eq!(
+            split_git_source("git+ssh://git@host/repo@v1.0"),
+            (
+                "ssh://git@host/repo",
+                Some(GitRef::Named("v1.0".to_owned()))
+            )
+        );
+        assert_eq!(
+            split_git_source("git+git@host:repo#abc123"),
+            ("git@host:repo", Some(GitRef::Commit("abc123".to_owned())))
+        );
+    }
+
+    #[test]
+    fn git_cache_names() {
+        assert_eq!(
+            git_cache_name("git+https://github.com/kisslinux/kiss"),
+            "kiss"
+        );
+        assert_eq!(
+            git_cache_name("git+https://github.com/kisslinux/kiss#0d2a8d3"),
+            "kiss"
+        );
+        assert_eq!(git_cache_name("git+https://host/repo@release/1.0"), "repo");
+        assert_eq!(
+            git_cache_name("git+ssh://git@host/repo.git@v1.0"),
+            "repo.git"
+        );
+        assert_eq!(git_cache_name("git+git@host:repo"), "repo");
+        assert_eq!(git_cache_name("git+git@host:user/repo#abc123"), "repo");
+    }
+
+    // empty directory for a test, inside the system tmp directory
+    fn test_dir(name: &str) -> PathBuf {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("kiss-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // write a tarball, names are put in the header as is so unsafe paths
+    // can be tested
+    fn write_tar(path: &Path, entries: &[(&str, EntryType, &str)]) {
+        let mut builder: Builder<File> = Builder::new(File::create(path).unwrap());
+
+        for (name, entry_type, content) in entries {
+            let mut header: Header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o755);
+            let data: &str = if entry_type.is_symlink() {
+                header.set_link_name(content).unwrap();
+                ""
+            } else {
+                content
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data.as_bytes()).unwrap();
+        }
+
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn missing_sources_offline() {
+        let dir: PathBuf = test_dir("missing-sources");
+        let repo_dir: PathBuf = dir.join("repo").join("foo");
+        fs::create_dir_all(&repo_dir).unwrap();
+        fs::write(repo_dir.join("version"), "1.0 1\n").unwrap();
+        fs::write(repo_dir.join("patch"), "").unwrap();
+        fs::write(dir.join("local"), "").unwrap();
+
+        let local: String = dir.join("local").to_string_lossy().to_string();
+        let sources: String = format!(
+            "https://host/foo.tar.gz\ngit+https://host/bar@v1.0\n{}\n/nonexistent/baz.tar.gz\npatch\n",
+            local
+        );
+        fs::write(repo_dir.join("sources"), sources).unwrap();
+
+        let mut config: Config = Config::new();
+        config.offline = true;
+        config.kiss_path = vec![dir.join("repo").to_string_lossy().to_string()];
+        config.sources_dir = dir.join("sources");
+
+        match pkg_source_missing(&config, &["foo"]) {
+            Err(KissError::MissingSources(missing)) => assert_eq!(
+                missing,
+                vec![
+                    ("foo".to_owned(), "https://host/foo.tar.gz".to_owned()),
+                    ("foo".to_owned(), "git+https://host/bar@v1.0".to_owned()),
+                    ("foo".to_owned(), "/nonexistent/baz.tar.gz".to_owned()),
+                ]
+            ),
+            _ => panic!("expected missing sources"),
+        }
+
+        // nothing was copied or created in the sources cache
+        assert!(!config.sources_dir.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compression_from_magic() {
+        assert_eq!(
+            Compression::from_magic(&[0x1f, 0x8b, 0x08]),
+            Compression::Gzip
+        );
+        assert_eq!(Compression::from_magic(b"BZh91AY"), Compression::Bzip2);
+        assert_eq!(
+            Compression::from_magic(&[0x04, 0x22, 0x4d, 0x18, 0x64]),
+            Compression::Lz4
+        );
+        assert_eq!(
+            Compression::from_magic(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Compression::Xz
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        // too short to be xz
+        assert_eq!(
+            Compression::from_magic(&[0xfd, b'7', b'z']),
+            Compression::None
+        );
+        assert_eq!(Compression::from_magic(b""), Compression::None);
+        assert_eq!(Compression::from_magic(b"ustar"), Compression::None);
+    }
+
+    #[test]
+    fn compression_from_extension() {
+        assert_eq!(Compression::from_extension("gz"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_extension("zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_extension("xz.part"), None);
+        assert_eq!(Compression::from_extension("zip"), None);
+
+        assert!(Compression::None.check_supported().is_ok());
+        assert_eq!(
+            Compression::Gzip.check_supported().is_ok(),
+            cfg!(feature = "gzip")
+        );
+        assert_eq!(
+            Compression::Zstd.check_supported().is_ok(),
+            cfg!(feature = "zstd")
+        );
+    }
+
+    #[test]
+    fn compress_levels() {
+        assert_eq!(compress_level_for("gz", None).unwrap(), 6);
+        assert_eq!(compress_level_for("zst", None).unwrap(), 3);
+        assert_eq!(compress_level_for("lz4", None).unwrap(), 0);
+        assert_eq!(compress_level_for("xz", Some(9)).unwrap(), 9);
+        assert_eq!(compress_level_for("zst", Some(22)).unwrap(), 22);
+        assert_eq!(compress_level_for("gz", Some(0)).unwrap(), 0);
+
+        assert!(compress_level_for("gz", Some(10)).is_err());
+        assert!(compress_level_for("bz2", Some(0)).is_err());
+        assert!(compress_level_for("zst", Some(23)).is_err());
+    }
+
+    #[test]
+    fn safe_paths() {
+        assert!(is_safe_path(Path::new("foo/bar")));
+        assert!(is_safe_path(Path::new("./foo/bar")));
+        assert!(!is_safe_path(Path::new("../foo")));
+        assert!(!is_safe_path(Path::new("foo/../../bar")));
+        assert!(!is_safe_path(Path::new("/etc/passwd")));
+    }
+
+    #[test]
+    fn leading_dirs() {
+        let dir = |path: &str| (PathBuf::from(path), true);
+        let file = |path: &str| (PathBuf::from(path), false);
+
+        assert_eq!(
+            leading_dir(&[dir("foo-1.0/"), file("foo-1.0/README"), dir("foo-1.0/src")]),
+            Some(PathBuf::from("foo-1.0"))
+        );
+        assert_eq!(
+            leading_dir(&[dir("./"), file("./foo-1.0/README")]),
+            Some(PathBuf::from("foo-1.0"))
+        );
+        assert_eq!(leading_dir(&[file("foo-1.0/README"), file("bar")]), None);
+        assert_eq!(leading_dir(&[file("foo/README"), file("bar/README")]), None);
+        assert_eq!(leading_dir(&[file("README")]), None);
+    }
+
+    #[test]
+    fn strip_paths() {
+        let leading: Option<&Path> = Some(Path::new("foo-1.0"));
+
+        assert_eq!(
+            strip_path(Path::new("./foo-1.0/src/main.c"), leading),
+            Some(PathBuf::from("src/main.c"))
+        );
+        assert_eq!(strip_path(Path::new("foo-1.0/"), leading), None);
+        assert_eq!(strip_path(Path::new("bar/main.c"), leading), None);
+        assert_eq!(
+            strip_path(Path::new("./bar/main.c"), None),
+            Some(PathBuf::from("bar/main.c"))
+        );
+    }
+
+    #[test]
+    fn symlinked_paths() {
+        let dir: PathBuf = test_dir("symlinked-paths");
+        fs::create_dir(dir.join("real")).unwrap();
+        std::os::unix::fs::symlink("/tmp", dir.join("link")).unwrap();
+
+        assert!(!through_symlink(&dir, Path::new("real/file"), false));
+        assert!(through_symlink(&dir, Path::new("link/file"), false));
+        assert!(through_symlink(&dir, Path::new("./link/file"), false));
+        // a file replaces the symlink, a directory would follow it
+        assert!(!through_symlink(&dir, Path::new("link"), false));
+        assert!(through_symlink(&dir, Path::new("link"), true));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn assert_unsafe(name: &str, entries: &[(&str, EntryType, &str)]) -> PathBuf {
+        let dir: PathBuf = test_dir(name);
+        let tarball: PathBuf = dir.join("test.tar");
+        let extract_path: PathBuf = dir.join("extract");
+        fs::create_dir(&extract_path).unwrap();
+        write_tar(&tarball, entries);
+
+        match pkg_source_tar(&tarball.to_string_lossy(), &extract_path, false, false) {
+            Err(KissError::UnsafeArchive { .. }) => {}
+            result => panic!("expected an unsafe archive, got {:?}", result),
+        }
+
+        dir
+    }
+
+    #[test]
+    fn extract_parent_path() {
+        let dir: PathBuf = assert_unsafe(
+            "extract-parent-path",
+            &[
+                ("../escaped", EntryType::Regular, "x"),
+                ("after", EntryType::Regular, "x"),
+            ],
+        );
+
+        assert!(!dir.join("escaped").exists());
+        // extraction stops at the first unsafe entry
+        assert!(!dir.join("extract/after").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_absolute_path() {
+        let dir: PathBuf = test_dir("extract-absolute-target");
+        let target: String = format!("{}/escaped", dir.display());
+
+        let test: PathBuf = assert_unsafe(
+            "extract-absolute-path",
+            &[(target.as_str(), EntryType::Regular, "x")],
+        );
+
+        assert!(!Path::new(&target).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(test).unwrap();
+    }
+
+    #[test]
+    fn extract_through_symlink() {
+        let outside: PathBuf = test_dir("extract-symlink-target");
+
+        let dir: PathBuf = assert_unsafe(
+            "extract-through-symlink",
+            &[
+                ("link", EntryType::Symlink, &outside.to_string_lossy()),
+                ("link/escaped", EntryType::Regular, "x"),
+            ],
+        );
+
+        assert!(!outside.join("escaped").exists());
+
+        fs::remove_dir_all(outside).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn extract_safe_archive() {
+        let dir: PathBuf = test_dir("extract-safe-archive");
+        let tarball: PathBuf = dir.join("test.tar");
+        let extract_path: PathBuf = dir.join("extract");
+        fs::create_dir(&extract_path).unwrap();
+        write_tar(
+            &tarball,
+            &[
+                ("foo-1.0/", EntryType::Directory, ""),
+                ("foo-1.0/file", EntryType::Regular, "content"),
+                ("foo-1.0/link", EntryType::Symlink, "file"),
+            ],
+        );
+
+        pkg_source_tar(&tarball.to_string_lossy(), &extract_path, true, false).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(extract_path.join("link")).unwrap(),
+            "content"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
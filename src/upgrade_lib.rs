@@ -1,47 +1,42 @@
 use crate::shared_lib::{
     globals::{Config, Dependencies},
-    prompt, read_a_dir_and_sort,
+    prompt, read_a_dir_and_sort, KissError,
 };
 
 use std::path::PathBuf;
 
-use crate::shared_lib::signal::pkg_clean;
-use crate::{die, log};
+use crate::log;
 
 use crate::build_lib::pkg_build_all;
 use crate::search_lib::pkg_find_version;
 
-pub fn pkg_upgrade(config: &Config, dependencies: &mut Dependencies) {
+pub fn pkg_upgrade(config: &Config, dependencies: &mut Dependencies) -> Result<(), KissError> {
     log!("Checking for new package versions");
 
     let installed_packages: Vec<PathBuf> =
-        read_a_dir_and_sort(config.sys_db.to_string_lossy().to_string(), false, &[]);
+        read_a_dir_and_sort(config.sys_db.to_string_lossy().to_string(), false, &[])?;
 
-    let packages: Vec<String> = installed_packages
-        .iter()
-        .filter_map(|path| {
-            let path_str: String = path.to_string_lossy().to_string();
-            let pkg_name: &str = path_str
-                .rsplit('/')
-                .next()
-                .unwrap_or_else(|| die!("Failed to get package name"));
-            let old_ver: String = pkg_find_version(
-                config,
-                pkg_name,
-                Some(&config.sys_db.to_string_lossy().to_string()),
-            )
-            .unwrap_or_else(|| die!(pkg_name, "Failed to get version"));
-            let new_ver: String = pkg_find_version(config, pkg_name, None)
-                .unwrap_or_else(|| die!(pkg_name, "Failed to get version"));
+    let mut packages: Vec<String> = Vec::new();
 
-            if old_ver != new_ver {
-                println!("{pkg_name} {old_ver} => {new_ver}");
-                Some(pkg_name.to_owned())
-            } else {
-                None
-            }
-        })
-        .collect();
+    for path in installed_packages {
+        let pkg_name: String = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let old_ver: String = pkg_find_version(
+            config,
+            &pkg_name,
+            Some(&config.sys_db.to_string_lossy().to_string()),
+        )
+        .ok_or_else(|| KissError::NotInstalled(pkg_name.to_owned()))?;
+        let new_ver: String = pkg_find_version(config, &pkg_name, None)
+            .ok_or_else(|| KissError::NotFound(pkg_name.to_owned()))?;
+
+        if old_ver != new_ver {
+            println!("{pkg_name} {old_ver} => {new_ver}");
+            packages.push(pkg_name);
+        }
+    }
 
     if packages.contains(&String::from("kiss")) {
         log!("Detected package manager update");
@@ -53,7 +48,7 @@ pub fn pkg_upgrade(config: &Config, dependencies: &mut Dependencies) {
 
         log!("Updated the package manager");
         log!("Re-run 'kiss-upgrade' to update your system");
-        return;
+        return Ok(());
     }
 
     if !packages.is_empty() {
@@ -65,9 +60,11 @@ pub fn pkg_upgrade(config: &Config, dependencies: &mut Dependencies) {
         if config.prompt {
             prompt(None);
         }
-        pkg_build_all(config, dependencies, packages);
+        pkg_build_all(config, dependencies, packages)?;
         log!("Updated all packages");
     } else {
         log!("Nothing to do")
     }
+
+    Ok(())
 }